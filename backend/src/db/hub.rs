@@ -0,0 +1,25 @@
+//! In-process fan-out of newly created notifications, this is what the
+//! notification event stream listens on.
+
+use crate::db::prisma::notification;
+use lazy_static::lazy_static;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+/// How many notifications a slow subscriber can fall behind by before it
+/// starts missing them
+const CAPACITY: usize = 256;
+
+lazy_static! {
+    static ref NOTIFICATION_HUB: Sender<notification::Data> = broadcast::channel(CAPACITY).0;
+}
+
+/// Sends the notification to every current subscriber. Having none is fine.
+pub fn publish(notif: notification::Data) {
+    let _ = NOTIFICATION_HUB.send(notif);
+}
+
+/// Every subscriber receives every notification, filtering by recipient is
+/// left to the caller.
+pub fn subscribe() -> Receiver<notification::Data> {
+    NOTIFICATION_HUB.subscribe()
+}
@@ -3,8 +3,11 @@
 //!
 //! [`prisma`]: crate::db::prisma
 
-use crate::db::prisma::Category;
-use rocket::request::FromParam;
+use crate::db::prisma::{Category, NotificationType};
+use rocket::{
+    form::{self, FromFormField, ValueField},
+    request::FromParam,
+};
 
 impl Category {
     pub const ALL: [Category; 4] = [
@@ -28,3 +31,23 @@ impl<'a> FromParam<'a> for Category {
         })
     }
 }
+
+impl<'v> FromFormField<'v> for Category {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        Category::from_param(field.value).map_err(|_| form::Error::validation("invalid section").into())
+    }
+}
+
+impl NotificationType {
+    pub const ALL: [NotificationType; 9] = [
+        NotificationType::Approval,
+        NotificationType::Rejection,
+        NotificationType::PostDeleted,
+        NotificationType::PostEdited,
+        NotificationType::SubmissionAssigned,
+        NotificationType::Announcement,
+        NotificationType::Digest,
+        NotificationType::CommentReply,
+        NotificationType::ReportResolved,
+    ];
+}
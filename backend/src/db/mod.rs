@@ -1,3 +1,4 @@
+pub mod hub;
 pub mod impls;
 #[allow(warnings, unused)]
 pub mod prisma;
@@ -1,66 +1,255 @@
 use crate::{
     db::{
-        prisma,
+        hub, prisma,
         prisma::{
-            image, notification, pending_image, pending_post, post,
+            audit_event, bookmark, canned_response, collection, comment, daily_post, excerpt_band, image, job_run,
+            notification, notification_preference, pending_image, pending_post, post, post_view, reaction,
+            reaction_count,
             read_filters::{BoolFilter, StringFilter},
-            user, Category, NotificationType, PrismaClient,
+            rejection, rejection_reason, rejection_reason_link, report, report_filing, review_vote, section_policy,
+            upload, user, webhook, webhook_delivery, webhook_subscription, AuditAction, Category, CommentStatus,
+            NotificationDelivery, NotificationType, PrismaClient, ReactionKind, ReportAction, ReportReason, Role,
+            SubmissionStatus, WebhookDeliveryStatus, WebhookEvent,
         },
     },
-    routes::utils::{jwt::generate_api_token, misc::PaginationFields},
+    routes::utils::{
+        jwt::generate_api_token,
+        misc::{
+            convert_and_sanitize, sanitize_alt_text, sanitize_image_text, Cursor, ImageMetadataEdit, PaginationFields,
+        },
+    },
+    similarity::{Shingles, MIN_SIMILARITY},
+    webhooks::{self, Event},
 };
 use async_once::AsyncOnce;
-use chrono::DateTime;
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, Utc};
 use color_eyre::eyre::Context;
 use lazy_static::lazy_static;
-use prisma_client_rust::{Direction, QueryError};
-use std::str::FromStr;
+use prisma_client_rust::{and, or, Direction, PrismaValue, QueryError, Raw};
+use sha2::{Digest, Sha256};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet},
+    env,
+    str::FromStr,
+};
 use uuid::Uuid;
 
 lazy_static! {
     pub static ref PRISMA_CLIENT: AsyncOnce<PrismaClient> =
         AsyncOnce::new(async { prisma::new_client().await.unwrap() });
+    /// How many days have to pass before a post can be featured again
+    static ref DAILY_POST_WINDOW_DAYS: i64 = env::var("DAILY_POST_WINDOW_DAYS")
+        .ok()
+        .and_then(|d| d.parse().ok())
+        .unwrap_or(30);
+    /// Comments from accounts younger than this are held for moderation
+    static ref NEW_ACCOUNT_HOLD: Duration = Duration::days(
+        env::var("NEW_ACCOUNT_COMMENT_HOLD_DAYS")
+            .ok()
+            .and_then(|d| d.parse().ok())
+            .unwrap_or(3)
+    );
+    /// How long a reviewer's claim on a submission lasts
+    static ref REVIEW_CLAIM: Duration = Duration::minutes(
+        env::var("REVIEW_CLAIM_MINUTES")
+            .ok()
+            .and_then(|m| m.parse().ok())
+            .unwrap_or(30)
+    );
+    /// Accounts younger than this can't submit anything yet
+    static ref SUBMISSION_MIN_ACCOUNT_AGE: Duration = Duration::hours(
+        env::var("SUBMISSION_MIN_ACCOUNT_AGE_HOURS")
+            .ok()
+            .and_then(|h| h.parse().ok())
+            .unwrap_or(24)
+    );
+    static ref USER_QUOTA: Quota = Quota::from_env("USER", 5, 20);
+    static ref ADMIN_QUOTA: Quota = Quota::from_env("ADMIN", 50, 500);
 }
 
 macro_rules! table_helper {
-    ($($table:ident),*) => {
+    ($($table:ident $(=> $plural:ident)?),*) => {
+        $(
+            table_helper!(@fn $table $(=> $plural)?);
+        )*
+    };
+    (@fn $table:ident) => {
         paste::paste! {
-            $(
-                pub async fn [<$table s>]<'a>() -> $table::Actions<'a> {
-                    PRISMA_CLIENT.get().await.$table()
-                }
-            )*
+            table_helper!(@fn $table => [<$table s>]);
         }
-    }
+    };
+    (@fn $table:ident => $plural:ident) => {
+        pub async fn $plural<'a>() -> $table::Actions<'a> {
+            PRISMA_CLIENT.get().await.$table()
+        }
+    };
 }
 
-table_helper!(user, post, pending_post, image, pending_image, notification);
+table_helper!(
+    user,
+    post,
+    pending_post,
+    image,
+    pending_image,
+    notification,
+    notification_preference,
+    job_run,
+    webhook,
+    webhook_subscription,
+    webhook_delivery => webhook_deliveries,
+    daily_post,
+    collection,
+    bookmark,
+    reaction,
+    reaction_count,
+    post_view,
+    comment,
+    report,
+    report_filing,
+    audit_event,
+    review_vote,
+    section_policy => section_policies,
+    rejection_reason,
+    canned_response,
+    rejection,
+    rejection_reason_link,
+    upload,
+    excerpt_band
+);
 
+/// Pages through posts or pending posts, sorted by `$at` and then their IDs.
+/// Uses the pagination's cursor when it has one, falling back to offsets
+/// otherwise. Random orders are always paged with offsets.
 macro_rules! find_in_posts {
-    (post, $pagination:ident, $($filter:expr),*) => {
-        find_in_posts!(post::confirmed_at::order(Direction::Desc), $pagination, post => $($filter),*)
-    };
-    (pending_post, $pagination:ident, $($filter:expr),*) => {
-        find_in_posts!(pending_post::submitted_at::order(Direction::Desc), $pagination, pending_post => $($filter),*)
+    (post, $at:ident, $filters:expr, $order:expr, $pagination:ident) => {
+        find_in_posts!(
+            post,
+            $at,
+            $filters,
+            $order,
+            $pagination
+                with post::images::fetch(vec![]).order_by(image::order::order(Direction::Asc)),
+                with post::reaction_counts::fetch(vec![])
+        )
     };
-    ($order:expr, $pagination:ident, $post:ident => $($filter:expr),*) => {
-        paste::paste! {
-            [<$post s>]()
-                .await
-                .find_many(vec![
-                    $(
-                        $filter,
-                    )*
-                ])
-                .order_by($order)
-                .skip($pagination.skip())
-                .take($pagination.per_page.into())
-                .exec()
-                .await
-        }
+    ($post:ident, $at:ident, $filters:expr, $order:expr, $pagination:ident $(with $with:expr),*) => {
+        paste::paste! {{
+            let filters: Vec<$post::WhereParam> = $filters;
+            let total = [<$post s>]().await.count(filters.clone()).exec().await?;
+
+            if let PostOrder::Random { seed } = $order {
+                let mut ids = [<$post s>]()
+                    .await
+                    .find_many(filters)
+                    .select($post::select!({ id }))
+                    .exec()
+                    .await?
+                    .into_iter()
+                    .map(|p| p.id)
+                    .collect::<Vec<_>>();
+
+                seeded_shuffle(&mut ids, seed);
+
+                let ids = ids
+                    .into_iter()
+                    .skip($pagination.skip() as usize)
+                    .take($pagination.per_page() as usize)
+                    .collect::<Vec<_>>();
+
+                let mut items = [<$post s>]()
+                    .await
+                    .find_many(vec![$post::id::in_vec(ids.clone())])
+                    $(.with($with))*
+                    .exec()
+                    .await?;
+
+                items.sort_by_key(|p| ids.iter().position(|id| *id == p.id));
+
+                let next = NextPage::after_offset(&$pagination, items.len(), total);
+
+                Ok(Page { items, total, next })
+            } else {
+                let ascending = $order == PostOrder::Oldest;
+                let direction = || if ascending { Direction::Asc } else { Direction::Desc };
+
+                // The ID breaks ties between items with the same timestamp
+                let (filters, skip) = match &$pagination.cursor {
+                    Some(Cursor { at, id }) => {
+                        let mut filters = filters;
+                        filters.push(if ascending {
+                            or![
+                                $post::$at::gt(*at),
+                                and![$post::$at::equals(*at), $post::id::gt(id.clone())]
+                            ]
+                        } else {
+                            or![
+                                $post::$at::lt(*at),
+                                and![$post::$at::equals(*at), $post::id::lt(id.clone())]
+                            ]
+                        });
+
+                        (filters, 0)
+                    }
+                    None => (filters, $pagination.skip()),
+                };
+
+                let per_page = $pagination.per_page() as usize;
+
+                // One extra is fetched to tell whether there's a next page
+                let mut items = [<$post s>]()
+                    .await
+                    .find_many(filters)
+                    $(.with($with))*
+                    .order_by($post::$at::order(direction()))
+                    .order_by($post::id::order(direction()))
+                    .skip(skip)
+                    .take(i64::from($pagination.per_page()) + 1)
+                    .exec()
+                    .await?;
+
+                let next = if items.len() > per_page {
+                    items.truncate(per_page);
+                    items.last().map(|p| NextPage::Cursor(Cursor::new(p.$at, p.id.clone())))
+                } else {
+                    None
+                };
+
+                Ok(Page { items, total, next })
+            }
+        }}
     };
 }
 
+/// Shuffles the same way every time for a given seed, so random orders can be
+/// paged through
+fn seeded_shuffle(ids: &mut [String], seed: u64) {
+    ids.sort_by_cached_key(|id| Sha256::digest(format!("{seed}.{id}")).to_vec());
+}
+
+/// One page of a listing, along with how many items there are in total
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    /// Where the next page starts, [`None`] on the last one
+    pub next: Option<NextPage>,
+}
+
+/// How the next page of a listing is asked for
+pub enum NextPage {
+    Cursor(Cursor),
+    /// The page number, for orders that can only be paged with offsets
+    Offset(u32),
+}
+
+impl NextPage {
+    /// The page after the one `pagination` points at, if there are items past
+    /// the `count` on it
+    fn after_offset(pagination: &PaginationFields, count: usize, total: i64) -> Option<NextPage> {
+        (pagination.skip() + (count as i64) < total).then(|| NextPage::Offset(pagination.page.max(1) + 1))
+    }
+}
+
 pub async fn create_user(id: Uuid, username: String, password: String) -> Result<String, Box<dyn std::error::Error>> {
     users()
         .await
@@ -80,14 +269,12 @@ pub async fn get_user(username: String) -> Option<user::Data> {
         .unwrap()
 }
 
-pub async fn get_user_notifications(
-    user: Uuid,
-    which: WhichNotifications,
-    pagination: PaginationFields,
-) -> Result<Vec<notification::Data>, QueryError> {
-    let mut filters = vec![notification::WhereParam::RecipientId(StringFilter::Equals(
-        user.to_string(),
-    ))];
+fn user_notification_filters(user: Uuid, which: WhichNotifications) -> Vec<notification::WhereParam> {
+    let mut filters = vec![
+        notification::WhereParam::RecipientId(StringFilter::Equals(user.to_string())),
+        notification::held::equals(false),
+        unexpired_notification(),
+    ];
 
     match which {
         WhichNotifications::Read => filters.push(notification::WhereParam::Read(BoolFilter::Equals(true))),
@@ -95,12 +282,54 @@ pub async fn get_user_notifications(
         WhichNotifications::All => {}
     }
 
+    filters
+}
+
+pub async fn get_user_notifications(
+    user: Uuid,
+    which: WhichNotifications,
+    pagination: PaginationFields,
+) -> Result<Vec<notification::Data>, QueryError> {
     notifications()
         .await
-        .find_many(filters)
+        .find_many(user_notification_filters(user, which))
         .order_by(notification::created_at::order(Direction::Desc))
         .skip(pagination.skip())
-        .take(pagination.per_page.into())
+        .take(pagination.per_page().into())
+        .exec()
+        .await
+}
+
+fn unexpired_notification() -> notification::WhereParam {
+    or![
+        notification::expires_at::equals(None),
+        notification::expires_at::gt(Utc::now().into())
+    ]
+}
+
+pub async fn count_user_notifications(user: Uuid, which: WhichNotifications) -> Result<i64, QueryError> {
+    notifications()
+        .await
+        .count(user_notification_filters(user, which))
+        .exec()
+        .await
+}
+
+/// Marks every unread notification as read, optionally only those created
+/// before `before`. Returns how many were updated.
+pub async fn mark_all_user_notifications_read(
+    user: Uuid,
+    before: Option<DateTime<FixedOffset>>,
+) -> Result<i64, QueryError> {
+    let mut filters = user_notification_filters(user, WhichNotifications::Unread);
+
+    if let Some(before) = before {
+        filters.push(notification::created_at::lt(before));
+    }
+
+    notifications()
+        .await
+        .update_many(filters, vec![notification::read::set(true)])
         .exec()
         .await
 }
@@ -112,34 +341,53 @@ pub enum WhichNotifications {
     All,
 }
 
+/// Takes the client so it can be part of a transaction
+#[allow(clippy::too_many_arguments)]
 pub async fn create_post(
+    client: &PrismaClient,
     category: Category,
     id: Uuid,
     author_id: Uuid,
     excerpt: String,
     citation: String,
-    submitted_at: DateTime<chrono::FixedOffset>,
+    excerpt_source: Option<String>,
+    citation_source: Option<String>,
+    submitted_at: DateTime<FixedOffset>,
+    publish_at: Option<DateTime<FixedOffset>>,
 ) -> Result<post::Data, QueryError> {
-    posts()
-        .await
+    let mut params = vec![
+        post::SetParam::SetId(id.to_string()),
+        post::SetParam::SetSubmittedAt(submitted_at),
+        post::excerpt_source::set(excerpt_source),
+        post::citation_source::set(citation_source),
+    ];
+
+    params.extend(publish_at.map(post::confirmed_at::set));
+
+    let post = client
+        .post()
         .create(
             user::UniqueWhereParam::IdEquals(author_id.to_string()),
             category,
             excerpt,
             citation,
-            vec![
-                post::SetParam::SetId(id.to_string()),
-                post::SetParam::SetSubmittedAt(submitted_at),
-            ],
+            params,
         )
         .exec()
-        .await
+        .await?;
+
+    index_excerpt(client, ExcerptOwner::Post(post.id.clone()), &post.excerpt).await?;
+
+    Ok(post)
 }
 
+/// Also finds scheduled posts, see [`get_published_post`] for everyone else
 pub async fn get_post(category: Category, id: String) -> Result<Option<post::Data>, QueryError> {
     posts()
         .await
         .find_first(vec![post::category::equals(category), post::id::equals(id)])
+        .with(post::images::fetch(vec![]).order_by(image::order::order(Direction::Asc)))
+        .with(post::reaction_counts::fetch(vec![]))
         .exec()
         .await
 }
@@ -152,242 +400,2787 @@ pub async fn get_post_by_id(id: String) -> Result<Option<post::Data>, QueryError
         .await
 }
 
-pub async fn get_section_posts(
-    category: Category,
-    pagination: PaginationFields,
-) -> Result<Vec<post::Data>, QueryError> {
-    find_in_posts!(post, pagination, post::category::equals(category))
+/// How post and pending post listings are sorted
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum PostOrder {
+    /// Most recently confirmed first, or submitted for pending posts
+    Newest,
+    Oldest,
+    /// Most recently submitted first
+    Submitted,
+    Random {
+        seed: u64,
+    },
+    /// Most reacted to and viewed first, favoring recent posts. Pending posts
+    /// are sorted as [`PostOrder::Newest`] instead.
+    Popular,
 }
 
-pub async fn get_user_posts(author_id: String, pagination: PaginationFields) -> Result<Vec<post::Data>, QueryError> {
-    find_in_posts!(post, pagination, post::author_id::equals(author_id))
+/// Narrows down post and pending post listings, anything left as [`None`]
+/// matches everything. The date range is checked against when posts were
+/// confirmed, and when pending posts were submitted.
+#[derive(Clone, Default)]
+pub struct PostFilter {
+    pub category: Option<Category>,
+    pub author_id: Option<String>,
+    /// Inclusive
+    pub since: Option<DateTime<FixedOffset>>,
+    /// Exclusive
+    pub until: Option<DateTime<FixedOffset>>,
+    /// Posts scheduled for later are hidden from everything public, so this
+    /// should only be set for admins
+    pub include_scheduled: bool,
+    /// Only submissions nobody is reviewing, ignored for posts
+    pub unclaimed: bool,
 }
 
-pub async fn get_user_posts_in_section(
-    category: Category,
-    author_id: String,
+impl PostFilter {
+    fn post_params(self) -> Vec<post::WhereParam> {
+        let mut params = vec![];
+
+        if !self.include_scheduled {
+            params.push(post::confirmed_at::lte(Utc::now().into()));
+        }
+
+        params.extend(self.category.map(post::category::equals));
+        params.extend(self.author_id.map(post::author_id::equals));
+        params.extend(self.since.map(post::confirmed_at::gte));
+        params.extend(self.until.map(post::confirmed_at::lt));
+
+        params
+    }
+
+    /// Drafts are left out, only their authors get to see them
+    fn pending_post_params(self) -> Vec<pending_post::WhereParam> {
+        let mut params = vec![pending_post::status::equals(SubmissionStatus::Queued)];
+
+        params.extend(self.category.map(pending_post::category::equals));
+        params.extend(self.author_id.map(pending_post::author_id::equals));
+        params.extend(self.since.map(pending_post::submitted_at::gte));
+        params.extend(self.until.map(pending_post::submitted_at::lt));
+
+        if self.unclaimed {
+            params.push(or![
+                pending_post::reviewer_id::equals(None),
+                pending_post::claimed_until::equals(None),
+                pending_post::claimed_until::lt(Utc::now().into())
+            ]);
+        }
+
+        params
+    }
+}
+
+pub async fn get_posts(
+    filter: PostFilter,
+    order: PostOrder,
     pagination: PaginationFields,
-) -> Result<Vec<post::Data>, QueryError> {
-    find_in_posts!(
-        post,
-        pagination,
-        post::category::equals(category),
-        post::author_id::equals(author_id)
-    )
+) -> Result<Page<post::Data>, QueryError> {
+    let filters = filter.post_params();
+
+    match order {
+        PostOrder::Submitted => find_in_posts!(post, submitted_at, filters, order, pagination),
+        // Scores aren't unique enough to page by, so this uses offsets
+        PostOrder::Popular => {
+            let total = posts().await.count(filters.clone()).exec().await?;
+
+            let items = posts()
+                .await
+                .find_many(filters)
+                .with(post::images::fetch(vec![]).order_by(image::order::order(Direction::Asc)))
+                .with(post::reaction_counts::fetch(vec![]))
+                .order_by(post::popularity::order(Direction::Desc))
+                .order_by(post::id::order(Direction::Desc))
+                .skip(pagination.skip())
+                .take(pagination.per_page().into())
+                .exec()
+                .await?;
+
+            let next = NextPage::after_offset(&pagination, items.len(), total);
+
+            Ok(Page { items, total, next })
+        }
+        _ => find_in_posts!(post, confirmed_at, filters, order, pagination),
+    }
 }
 
-pub async fn remove_post(category: Category, id: String) -> Result<i64, QueryError> {
-    posts()
+/// The number of posts in the section, or all of them, along with when the
+/// latest one was confirmed. Cheap enough to check before building a feed.
+pub async fn get_posts_state(category: Option<Category>) -> Result<(i64, Option<DateTime<FixedOffset>>), QueryError> {
+    let filters = || {
+        PostFilter {
+            category,
+            ..Default::default()
+        }
+        .post_params()
+    };
+
+    let count = posts().await.count(filters()).exec().await?;
+    let latest = posts()
         .await
-        .delete_many(vec![post::category::equals(category), post::id::equals(id)])
+        .find_first(filters())
+        .order_by(post::confirmed_at::order(Direction::Desc))
         .exec()
+        .await?;
+    let edited = posts()
         .await
+        .find_first(filters())
+        .order_by(post::updated_at::order(Direction::Desc))
+        .exec()
+        .await?;
+
+    let last_modified = latest.map(|p| p.confirmed_at).max(edited.map(|p| p.updated_at));
+
+    Ok((count, last_modified))
 }
 
-// TODO: Minimize code duplication
+/// Returns the updated post, fails if it doesn't exist. Counts as an edit for
+/// the post's `updated_at`.
+pub async fn update_post(id: String, mut params: Vec<post::SetParam>) -> Result<post::Data, QueryError> {
+    let reindex = params.iter().any(|p| matches!(p, post::SetParam::SetExcerpt(_)));
 
-pub async fn create_pending_post(
-    category: Category,
-    id: Uuid,
-    author_id: Uuid,
-    excerpt: String,
-    citation: String,
-) -> Result<pending_post::Data, QueryError> {
-    pending_posts()
+    params.push(post::updated_at::set(Utc::now().into()));
+
+    PRISMA_CLIENT
+        .get()
         .await
-        .create(
-            user::UniqueWhereParam::IdEquals(author_id.to_string()),
-            category,
-            excerpt,
-            citation,
-            vec![pending_post::SetParam::SetId(id.to_string())],
-        )
-        .exec()
+        ._transaction()
+        .run(|client| async move {
+            let post = client
+                .post()
+                .update(post::UniqueWhereParam::IdEquals(id), params)
+                .exec()
+                .await?;
+
+            if reindex {
+                index_excerpt(&client, ExcerptOwner::Post(post.id.clone()), &post.excerpt).await?;
+            }
+
+            Ok(post)
+        })
         .await
 }
 
-pub async fn get_pending_post(category: Category, id: String) -> Result<Option<pending_post::Data>, QueryError> {
-    pending_posts()
+/// Marks the post as edited, for when only its images changed
+async fn touch_post(post_id: String) -> Result<(), QueryError> {
+    posts()
         .await
-        .find_first(vec![
-            pending_post::category::equals(category),
-            pending_post::id::equals(id),
-        ])
+        .update(
+            post::UniqueWhereParam::IdEquals(post_id),
+            vec![post::updated_at::set(Utc::now().into())],
+        )
         .exec()
-        .await
+        .await?;
+
+    Ok(())
 }
 
-pub async fn get_pending_post_by_id(id: String) -> Result<Option<pending_post::Data>, QueryError> {
-    pending_posts()
+/// Removes the post along with its images, bookmarks of it and the days it
+/// was featured on. The image files are left to the caller.
+pub async fn remove_post(category: Category, id: String) -> Result<i64, QueryError> {
+    PRISMA_CLIENT
+        .get()
         .await
-        .find_unique(pending_post::UniqueWhereParam::IdEquals(id))
-        .exec()
+        ._transaction()
+        .run(|client| async move {
+            // Images don't cascade, since their files have to be removed too
+            client
+                .image()
+                .delete_many(vec![image::post::is(vec![
+                    post::category::equals(category),
+                    post::id::equals(id.clone()),
+                ])])
+                .exec()
+                .await?;
+
+            client
+                .post()
+                .delete_many(vec![post::category::equals(category), post::id::equals(id)])
+                .exec()
+                .await
+        })
         .await
 }
 
-pub async fn get_section_pending_posts(
-    category: Category,
-    pagination: PaginationFields,
-) -> Result<Vec<pending_post::Data>, QueryError> {
-    find_in_posts!(pending_post, pagination, pending_post::category::equals(category))
+/// Hides posts that are scheduled for later
+pub async fn get_published_post(category: Category, id: String) -> Result<Option<post::Data>, QueryError> {
+    Ok(get_post(category, id).await?.filter(|p| p.confirmed_at <= Utc::now()))
 }
 
-pub async fn get_user_pending_posts(
-    author_id: String,
-    pagination: PaginationFields,
-) -> Result<Vec<pending_post::Data>, QueryError> {
-    find_in_posts!(pending_post, pagination, pending_post::author_id::equals(author_id))
+/// Like [`get_published_post`], for routes that only get the id
+pub async fn get_published_post_by_id(id: String) -> Result<Option<post::Data>, QueryError> {
+    Ok(get_post_by_id(id).await?.filter(|p| p.confirmed_at <= Utc::now()))
 }
 
-pub async fn get_user_pending_posts_in_section(
-    category: Category,
-    author_id: String,
-    pagination: PaginationFields,
-) -> Result<Vec<pending_post::Data>, QueryError> {
-    find_in_posts!(
-        pending_post,
-        pagination,
-        pending_post::category::equals(category),
-        pending_post::author_id::equals(author_id)
-    )
-}
+/// A random post from the section, or from all of them. The same seed picks
+/// the same post as long as none get added or removed.
+pub async fn get_random_post(category: Option<Category>, seed: Option<u64>) -> Result<Option<post::Data>, QueryError> {
+    let filters = || {
+        PostFilter {
+            category,
+            ..Default::default()
+        }
+        .post_params()
+    };
 
-pub async fn remove_pending_post(category: Category, id: String) -> Result<i64, QueryError> {
-    pending_posts()
-        .await
-        .delete_many(vec![
-            pending_post::category::equals(category),
-            pending_post::id::equals(id),
-        ])
-        .exec()
-        .await
-}
+    let count = posts().await.count(filters()).exec().await?;
 
-pub async fn create_image(post_id: String, path: String, width: i32, height: i32) -> Result<image::Data, QueryError> {
-    images()
+    if count == 0 {
+        return Ok(None);
+    }
+
+    let n = match seed {
+        Some(seed) => hash_to_u64(&seed.to_string()),
+        None => Uuid::new_v4().as_u128() as u64,
+    };
+
+    posts()
         .await
-        .create(post::UniqueWhereParam::IdEquals(post_id), path, width, height, vec![])
+        .find_first(filters())
+        .with(post::images::fetch(vec![]).order_by(image::order::order(Direction::Asc)))
+        .with(post::reaction_counts::fetch(vec![]))
+        .order_by(post::id::order(Direction::Asc))
+        .skip((n % count as u64) as i64)
         .exec()
         .await
 }
 
-pub async fn create_pending_image(
-    post_id: String,
-    path: String,
-    width: i32,
-    height: i32,
-) -> Result<pending_image::Data, QueryError> {
-    pending_images()
+/// The post featured on `date` for the section, or for the whole site. It's
+/// picked from the posts confirmed by the end of that day, leaving out the
+/// ones featured within [`DAILY_POST_WINDOW_DAYS`] of it unless there aren't
+/// any others.
+pub async fn get_daily_post(category: Option<Category>, date: NaiveDate) -> Result<Option<post::Data>, QueryError> {
+    let scope = category.map_or_else(|| "ALL".to_owned(), |c| c.to_string());
+    let day: DateTime<FixedOffset> =
+        DateTime::<Utc>::from_utc(date.and_hms_opt(0, 0, 0).expect("midnight is a valid time"), Utc).into();
+
+    let featured = |id: String| async move {
+        posts()
+            .await
+            .find_unique(post::UniqueWhereParam::IdEquals(id))
+            .with(post::images::fetch(vec![]).order_by(image::order::order(Direction::Asc)))
+            .with(post::reaction_counts::fetch(vec![]))
+            .exec()
+            .await
+    };
+
+    let existing = daily_posts()
         .await
-        .create(
-            pending_post::UniqueWhereParam::IdEquals(post_id),
-            path,
-            width,
-            height,
-            vec![],
-        )
+        .find_unique(daily_post::date_scope(day, scope.clone()))
         .exec()
-        .await
-}
+        .await?;
 
-pub async fn confirm_pending_post(
-    category: Category,
-    id: String,
-    comment: Option<String>,
-) -> Result<notification::Data, QueryError> {
-    let pending_post::Data {
-        author_id: uid,
-        excerpt,
-        citation,
-        submitted_at,
-        ..
-    } = match pending_posts()
+    if let Some(existing) = existing {
+        return featured(existing.post_id).await;
+    }
+
+    let window = Duration::days(*DAILY_POST_WINDOW_DAYS);
+    let recent = daily_posts()
         .await
-        .find_unique(pending_post::UniqueWhereParam::IdEquals(id.clone()))
+        .find_many(vec![
+            daily_post::scope::equals(scope.clone()),
+            daily_post::date::gt(day - window),
+            daily_post::date::lt(day + window),
+        ])
         .exec()
         .await?
-    {
-        Some(p) => p,
-        None => return Err(QueryError::Deserialize("Not Found".to_owned())),
-    };
+        .into_iter()
+        .map(|d| d.post_id)
+        .collect::<Vec<_>>();
 
-    remove_pending_post(category, id.clone()).await?;
+    let candidates = PostFilter {
+        category,
+        until: Some(day + Duration::days(1)),
+        ..Default::default()
+    }
+    .post_params();
 
-    let new_id = Uuid::new_v4();
+    let mut filters = candidates.clone();
+    filters.push(post::id::not_in_vec(recent));
 
-    create_post(
-        category,
-        new_id,
-        Uuid::from_str(uid.as_str()).unwrap(),
-        excerpt,
-        citation,
-        submitted_at,
-    )
-    .await?;
+    let mut count = posts().await.count(filters.clone()).exec().await?;
 
-    let notif = NotificationContent::PostApproval {
-        url: format!("/posts/{}?id={new_id}", category.to_string().to_ascii_lowercase()),
-        comment,
-    };
+    // Repeats are better than nothing when every post has been featured recently
+    if count == 0 {
+        filters = candidates;
+        count = posts().await.count(filters.clone()).exec().await?;
+    }
 
-    create_notification(uid, &notif).await
-}
+    if count == 0 {
+        return Ok(None);
+    }
 
-pub async fn reject_pending_post(
-    category: Category,
-    id: String,
-    comment: Option<String>,
-) -> Result<notification::Data, QueryError> {
-    let pending_post::Data {
-        author_id: uid,
-        excerpt,
-        citation,
-        ..
-    } = match pending_posts()
+    let pick = posts()
         .await
-        .find_unique(pending_post::UniqueWhereParam::IdEquals(id.clone()))
+        .find_first(filters)
+        .order_by(post::id::order(Direction::Asc))
+        .skip((hash_to_u64(&format!("{scope}.{date}")) % count as u64) as i64)
         .exec()
-        .await?
-    {
-        Some(p) => p,
-        None => return Err(QueryError::Deserialize("Not Found".to_owned())),
-    };
-
-    remove_pending_post(category, id.clone()).await?;
+        .await?;
 
-    let notif = NotificationContent::PostRejection {
-        comment,
-        excerpt,
-        citation,
+    let Some(pick) = pick else {
+        return Ok(None);
     };
 
-    create_notification(uid, &notif).await
-}
+    let created = daily_posts()
+        .await
+        .create(
+            day,
+            scope.clone(),
+            post::UniqueWhereParam::IdEquals(pick.id.clone()),
+            vec![],
+        )
+        .exec()
+        .await;
+
+    // Someone else picked one first
+    if created.is_err() {
+        if let Some(existing) = daily_posts()
+            .await
+            .find_unique(daily_post::date_scope(day, scope))
+            .exec()
+            .await?
+        {
+            return featured(existing.post_id).await;
+        }
+    }
+
+    featured(pick.id).await
+}
+
+fn hash_to_u64(s: &str) -> u64 {
+    let digest = Sha256::digest(s);
+
+    u64::from_be_bytes(digest[..8].try_into().expect("SHA-256 digests are 32 bytes"))
+}
+
+// TODO: Minimize code duplication
+
+/// Takes the author's markdown, and stores it along with the HTML rendered
+/// from it
+pub async fn create_pending_post(
+    category: Category,
+    id: Uuid,
+    author_id: Uuid,
+    excerpt: String,
+    citation: String,
+    status: SubmissionStatus,
+) -> Result<pending_post::Data, QueryError> {
+    PRISMA_CLIENT
+        .get()
+        .await
+        ._transaction()
+        .run(|client| async move {
+            let submission = client
+                .pending_post()
+                .create(
+                    user::UniqueWhereParam::IdEquals(author_id.to_string()),
+                    category,
+                    convert_and_sanitize(&excerpt),
+                    convert_and_sanitize(&citation),
+                    vec![
+                        pending_post::SetParam::SetId(id.to_string()),
+                        pending_post::status::set(status),
+                        pending_post::excerpt_source::set(Some(excerpt)),
+                        pending_post::citation_source::set(Some(citation)),
+                    ],
+                )
+                .exec()
+                .await?;
+
+            index_excerpt(
+                &client,
+                ExcerptOwner::Submission(submission.id.clone()),
+                &submission.excerpt,
+            )
+            .await?;
+
+            Ok(submission)
+        })
+        .await
+}
+
+/// How much an account can submit, set per role with
+/// `<ROLE>_PENDING_SUBMISSION_QUOTA` and `<ROLE>_DAILY_UPLOAD_QUOTA`
+#[derive(Clone, Copy)]
+pub struct Quota {
+    /// Submissions queued for review at once, drafts don't count
+    pub pending_submissions: i64,
+    /// Images added to submissions or drafts in the last 24 hours
+    pub daily_uploads: i64,
+}
+
+impl Quota {
+    fn from_env(role: &str, pending_submissions: i64, daily_uploads: i64) -> Self {
+        let var = |name: &str, default: i64| {
+            env::var(format!("{role}_{name}"))
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(default)
+        };
+
+        Self {
+            pending_submissions: var("PENDING_SUBMISSION_QUOTA", pending_submissions),
+            daily_uploads: var("DAILY_UPLOAD_QUOTA", daily_uploads),
+        }
+    }
+
+    pub fn for_role(role: Role) -> Self {
+        match role {
+            Role::User => *USER_QUOTA,
+            Role::Admin => *ADMIN_QUOTA,
+        }
+    }
+}
+
+/// Why an account isn't allowed to submit something right now
+#[derive(Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum SubmissionViolation {
+    AccountTooNew {
+        allowed_at: DateTime<FixedOffset>,
+    },
+    PendingQuota {
+        limit: i64,
+    },
+    UploadQuota {
+        limit: i64,
+        retry_at: DateTime<FixedOffset>,
+    },
+    /// The same excerpt was already posted or is waiting for review
+    DuplicateExcerpt,
+}
+
+/// Accounts from before sign up times were recorded are old enough
+fn check_account_age(author: &user::Data) -> Option<SubmissionViolation> {
+    let allowed_at = author.created_at? + *SUBMISSION_MIN_ACCOUNT_AGE;
+
+    (author.role != Role::Admin && allowed_at > Utc::now()).then_some(SubmissionViolation::AccountTooNew { allowed_at })
+}
+
+/// Checks a new submission by the author, with its excerpt already sanitized.
+/// Admins aren't held to the account age.
+pub async fn check_submission(author_id: String, excerpt: &str) -> Result<Option<SubmissionViolation>, QueryError> {
+    let Some(author) = get_user_by_id(author_id.clone()).await? else {
+        return Ok(None);
+    };
+
+    if let Some(violation) = check_account_age(&author) {
+        return Ok(Some(violation));
+    }
+
+    let limit = Quota::for_role(author.role).pending_submissions;
+
+    let queued = pending_posts()
+        .await
+        .count(vec![
+            pending_post::author_id::equals(author_id),
+            pending_post::status::equals(SubmissionStatus::Queued),
+        ])
+        .exec()
+        .await?;
+
+    if queued >= limit {
+        return Ok(Some(SubmissionViolation::PendingQuota { limit }));
+    }
+
+    let posted = posts()
+        .await
+        .count(vec![post::excerpt::equals(excerpt.to_owned())])
+        .exec()
+        .await?;
+
+    let queued = pending_posts()
+        .await
+        .count(vec![
+            pending_post::excerpt::equals(excerpt.to_owned()),
+            pending_post::status::equals(SubmissionStatus::Queued),
+        ])
+        .exec()
+        .await?;
+
+    if posted + queued > 0 {
+        return Ok(Some(SubmissionViolation::DuplicateExcerpt));
+    }
+
+    Ok(None)
+}
+
+/// Checks an image upload by the author against their daily quota. Admins
+/// aren't held to the account age.
+pub async fn check_upload(author_id: String) -> Result<Option<SubmissionViolation>, QueryError> {
+    let Some(author) = get_user_by_id(author_id.clone()).await? else {
+        return Ok(None);
+    };
+
+    if let Some(violation) = check_account_age(&author) {
+        return Ok(Some(violation));
+    }
+
+    let limit = Quota::for_role(author.role).daily_uploads;
+    let since: DateTime<FixedOffset> = (Utc::now() - Duration::days(1)).into();
+
+    let filters = || {
+        vec![
+            upload::user_id::equals(author_id.clone()),
+            upload::created_at::gte(since),
+        ]
+    };
+
+    let count = uploads().await.count(filters()).exec().await?;
+
+    if count >= limit {
+        // Room frees up once the oldest upload in the window is a day old
+        let oldest = uploads()
+            .await
+            .find_first(filters())
+            .order_by(upload::created_at::order(Direction::Asc))
+            .exec()
+            .await?
+            .map_or(since, |u| u.created_at);
+
+        return Ok(Some(SubmissionViolation::UploadQuota {
+            limit,
+            retry_at: oldest + Duration::days(1),
+        }));
+    }
+
+    Ok(None)
+}
+
+/// A post or queued submission worded like another excerpt
+#[derive(Serialize)]
+pub struct SimilarExcerpt {
+    pub id: String,
+    pub section: Category,
+    /// Still waiting for review rather than posted
+    pub pending: bool,
+    pub excerpt: String,
+    /// From 0 to 1, see [`Shingles::similarity`]
+    pub similarity: f64,
+}
+
+/// Whose excerpt the bands in [`index_excerpt`] are of
+enum ExcerptOwner {
+    Post(String),
+    Submission(String),
+}
+
+/// Replaces the bands the excerpt can be found by in [`find_similar_excerpts`].
+/// Takes the client so it can be part of a transaction.
+async fn index_excerpt(client: &PrismaClient, owner: ExcerptOwner, excerpt: &str) -> Result<(), QueryError> {
+    let (filter, link): (_, excerpt_band::SetParam) = match owner {
+        ExcerptOwner::Post(id) => (
+            excerpt_band::post_id::equals(Some(id.clone())),
+            excerpt_band::post_id::set(Some(id)),
+        ),
+        ExcerptOwner::Submission(id) => (
+            excerpt_band::pending_post_id::equals(Some(id.clone())),
+            excerpt_band::pending_post_id::set(Some(id)),
+        ),
+    };
+
+    let bands = Shingles::from_html(excerpt).bands();
+
+    client.excerpt_band().delete_many(vec![filter]).exec().await?;
+
+    if !bands.is_empty() {
+        client
+            .excerpt_band()
+            .create_many(
+                bands
+                    .into_iter()
+                    .map(|hash| excerpt_band::create_unchecked(hash, vec![link.clone()]))
+                    .collect(),
+            )
+            .exec()
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// How many posts and submissions [`index_unbanded_excerpts`] goes through per
+/// run
+const INDEX_BATCH: i64 = 500;
+
+/// Indexes posts and submissions from before excerpts were banded, returning
+/// how many it got through
+pub async fn index_unbanded_excerpts() -> Result<usize, QueryError> {
+    let client = PRISMA_CLIENT.get().await;
+
+    let unbanded_posts = posts()
+        .await
+        .find_many(vec![post::excerpt_bands::none(vec![])])
+        .take(INDEX_BATCH)
+        .select(post::select!({ id excerpt }))
+        .exec()
+        .await?;
+    let unbanded_pending = pending_posts()
+        .await
+        .find_many(vec![pending_post::excerpt_bands::none(vec![])])
+        .take(INDEX_BATCH)
+        .select(pending_post::select!({ id excerpt }))
+        .exec()
+        .await?;
+    let count = unbanded_posts.len() + unbanded_pending.len();
+
+    for p in unbanded_posts {
+        index_excerpt(client, ExcerptOwner::Post(p.id), &p.excerpt).await?;
+    }
+
+    for p in unbanded_pending {
+        index_excerpt(client, ExcerptOwner::Submission(p.id), &p.excerpt).await?;
+    }
+
+    Ok(count)
+}
+
+/// Posts and queued submissions at least [`MIN_SIMILARITY`] alike to the
+/// excerpt, the most alike first. The submission being compared is left out
+/// with `exclude_id`. Only excerpts sharing a band with it are compared, so a
+/// few similar ones may be missed.
+pub async fn find_similar_excerpts(excerpt: &str, exclude_id: String) -> Result<Vec<SimilarExcerpt>, QueryError> {
+    let shingles = Shingles::from_html(excerpt);
+
+    let candidates = excerpt_bands()
+        .await
+        .find_many(vec![excerpt_band::hash::in_vec(shingles.bands())])
+        .exec()
+        .await?;
+    let (post_ids, pending_ids): (BTreeSet<_>, BTreeSet<_>) = (
+        candidates.iter().filter_map(|b| b.post_id.clone()).collect(),
+        candidates.into_iter().filter_map(|b| b.pending_post_id).collect(),
+    );
+
+    let posted = posts()
+        .await
+        .find_many(vec![post::id::in_vec(post_ids.into_iter().collect())])
+        .exec()
+        .await?
+        .into_iter()
+        .map(|p| (p.id, p.category, false, p.excerpt));
+
+    let queued = pending_posts()
+        .await
+        .find_many(vec![
+            pending_post::id::in_vec(pending_ids.into_iter().collect()),
+            pending_post::status::equals(SubmissionStatus::Queued),
+            pending_post::id::not(exclude_id),
+        ])
+        .exec()
+        .await?
+        .into_iter()
+        .map(|p| (p.id, p.category, true, p.excerpt));
+
+    let mut similar = posted
+        .chain(queued)
+        .filter_map(|(id, section, pending, excerpt)| {
+            let similarity = shingles.similarity(&Shingles::from_html(&excerpt));
+
+            (similarity >= MIN_SIMILARITY).then_some(SimilarExcerpt {
+                id,
+                section,
+                pending,
+                excerpt,
+                similarity,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    similar.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+
+    Ok(similar)
+}
+
+/// Only finds submissions queued for review, not drafts
+pub async fn get_pending_post(category: Category, id: String) -> Result<Option<pending_post::Data>, QueryError> {
+    pending_posts()
+        .await
+        .find_first(vec![
+            pending_post::category::equals(category),
+            pending_post::id::equals(id),
+            pending_post::status::equals(SubmissionStatus::Queued),
+        ])
+        .with(pending_post::votes::fetch(vec![]).order_by(review_vote::created_at::order(Direction::Asc)))
+        .exec()
+        .await
+}
+
+pub async fn get_pending_post_by_id(id: String) -> Result<Option<pending_post::Data>, QueryError> {
+    pending_posts()
+        .await
+        .find_unique(pending_post::UniqueWhereParam::IdEquals(id))
+        .exec()
+        .await
+}
+
+pub async fn get_pending_posts(
+    filter: PostFilter,
+    order: PostOrder,
+    pagination: PaginationFields,
+) -> Result<Page<pending_post::Data>, QueryError> {
+    let order = match order {
+        PostOrder::Popular => PostOrder::Newest,
+        order => order,
+    };
+
+    find_in_posts!(
+        pending_post,
+        submitted_at,
+        filter.pending_post_params(),
+        order,
+        pagination
+    )
+}
+
+pub async fn remove_pending_post(category: Category, id: String) -> Result<i64, QueryError> {
+    pending_posts()
+        .await
+        .delete_many(vec![
+            pending_post::category::equals(category),
+            pending_post::id::equals(id),
+        ])
+        .exec()
+        .await
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn create_image(
+    post_id: String,
+    path: String,
+    width: i32,
+    height: i32,
+    alt_text: String,
+    transcription: Option<String>,
+    order: i32,
+    caption: Option<String>,
+) -> Result<image::Data, QueryError> {
+    let image = images()
+        .await
+        .create(
+            post::UniqueWhereParam::IdEquals(post_id.clone()),
+            path,
+            width,
+            height,
+            vec![
+                image::alt_text::set(alt_text),
+                image::transcription::set(transcription),
+                image::order::set(order),
+                image::caption::set(caption),
+            ],
+        )
+        .exec()
+        .await?;
+
+    touch_post(post_id).await?;
+
+    Ok(image)
+}
+
+pub async fn count_post_images(post_id: String) -> Result<i64, QueryError> {
+    images().await.count(vec![image::post_id::equals(post_id)]).exec().await
+}
+
+/// Applies every `(path, params)` pair to the matching image of the given post
+/// in a single transaction, so a reorder either fully applies or not at all.
+/// Fails with "Not Found" if any of the paths isn't one of the post's images.
+pub async fn update_post_images(
+    post_id: String,
+    patches: Vec<(String, Vec<image::SetParam>)>,
+) -> Result<(), QueryError> {
+    PRISMA_CLIENT
+        .get()
+        .await
+        ._transaction()
+        .run(|client| async move {
+            for (path, params) in patches {
+                let updated = client
+                    .image()
+                    .update_many(
+                        vec![image::post_id::equals(post_id.clone()), image::path::equals(path)],
+                        params,
+                    )
+                    .exec()
+                    .await?;
+
+                // Erroring out rolls back the images updated so far
+                if updated == 0 {
+                    return Err(QueryError::Deserialize("Not Found".to_owned()));
+                }
+            }
+
+            client
+                .post()
+                .update(
+                    post::UniqueWhereParam::IdEquals(post_id),
+                    vec![post::updated_at::set(Utc::now().into())],
+                )
+                .exec()
+                .await?;
+
+            Ok(())
+        })
+        .await
+}
+
+/// Returns the number of deleted rows, the file itself is left to the caller.
+pub async fn remove_image(path: String) -> Result<i64, QueryError> {
+    let Some(image) = images()
+        .await
+        .find_unique(image::UniqueWhereParam::PathEquals(path.clone()))
+        .exec()
+        .await?
+    else {
+        return Ok(0);
+    };
+
+    let deleted = images()
+        .await
+        .delete_many(vec![image::path::equals(path)])
+        .exec()
+        .await?;
+
+    touch_post(image.post_id).await?;
+
+    Ok(deleted)
+}
+
+/// Also logs the upload against the uploader's quota, see [`check_upload`]
+pub async fn create_pending_image(
+    uploader_id: String,
+    post_id: String,
+    path: String,
+    width: i32,
+    height: i32,
+    alt_text: String,
+    transcription: Option<String>,
+) -> Result<pending_image::Data, QueryError> {
+    PRISMA_CLIENT
+        .get()
+        .await
+        ._transaction()
+        .run(|client| async move {
+            client
+                .upload()
+                .create(user::UniqueWhereParam::IdEquals(uploader_id), vec![])
+                .exec()
+                .await?;
+
+            client
+                .pending_image()
+                .create(
+                    pending_post::UniqueWhereParam::IdEquals(post_id),
+                    path,
+                    width,
+                    height,
+                    vec![
+                        pending_image::alt_text::set(alt_text),
+                        pending_image::transcription::set(transcription),
+                    ],
+                )
+                .exec()
+                .await
+        })
+        .await
+}
+
+/// What came of a reviewer's approval, see [`confirm_pending_post`]
+pub enum Confirmation {
+    Published {
+        post_id: String,
+        notification: Option<notification::Data>,
+    },
+    /// The section's policy needs more approvals, the reviewer's claim is
+    /// released so someone else can review it
+    Awaiting { approvals: i64, required: i64 },
+}
+
+/// Records the reviewer's approval, and once the section's policy is met moves
+/// the pending post and its images over to the published tables. Any metadata
+/// edits in `image_edits` replace what was submitted with the image. `note` is
+/// for other reviewers, `comment` is passed on to the author. Posts with a
+/// `publish_at` stay hidden until then. Fails as `Locked` while another
+/// reviewer holds the claim.
+pub async fn confirm_pending_post(
+    category: Category,
+    id: String,
+    reviewer_id: String,
+    note: Option<String>,
+    comment: Option<String>,
+    image_edits: &[ImageMetadataEdit],
+    publish_at: Option<DateTime<FixedOffset>>,
+) -> Result<Confirmation, QueryError> {
+    let new_id = Uuid::new_v4();
+    let pending_id = id.clone();
+
+    // Everything from the claim check on happens in one transaction, so the
+    // approval is only counted if the submission is still there to publish
+    let outcome = PRISMA_CLIENT
+        .get()
+        .await
+        ._transaction()
+        .run(|client| async move {
+            let pending = client
+                .pending_post()
+                .find_first(vec![
+                    pending_post::category::equals(category),
+                    pending_post::id::equals(pending_id.clone()),
+                ])
+                .with(pending_post::images::fetch(vec![]))
+                .exec()
+                .await?
+                .ok_or_else(|| QueryError::Deserialize("Not Found".to_owned()))?;
+
+            if is_locked(&pending, &reviewer_id) {
+                return Err(QueryError::Deserialize("Locked".to_owned()));
+            }
+
+            client
+                .review_vote()
+                .upsert(
+                    review_vote::pending_post_id_reviewer_id(pending_id.clone(), reviewer_id.clone()),
+                    review_vote::create(
+                        pending_post::UniqueWhereParam::IdEquals(pending_id.clone()),
+                        user::UniqueWhereParam::IdEquals(reviewer_id.clone()),
+                        vec![review_vote::comment::set(note.clone())],
+                    ),
+                    vec![
+                        review_vote::comment::set(note),
+                        review_vote::created_at::set(Utc::now().into()),
+                    ],
+                )
+                .exec()
+                .await?;
+
+            let approvals = client
+                .review_vote()
+                .count(vec![review_vote::pending_post_id::equals(pending_id.clone())])
+                .exec()
+                .await?;
+            let required = client
+                .section_policy()
+                .find_unique(section_policy::UniqueWhereParam::CategoryEquals(category))
+                .exec()
+                .await?
+                .map_or(1, |p| i64::from(p.required_approvals));
+
+            if approvals < required {
+                // Released so someone else can review it
+                client
+                    .pending_post()
+                    .update_many(
+                        vec![
+                            pending_post::id::equals(pending_id),
+                            pending_post::reviewer_id::equals(Some(reviewer_id)),
+                        ],
+                        vec![
+                            pending_post::reviewer_id::set(None),
+                            pending_post::claimed_until::set(None),
+                        ],
+                    )
+                    .exec()
+                    .await?;
+
+                return Ok(Err(Confirmation::Awaiting { approvals, required }));
+            }
+
+            let pending_post::Data {
+                author_id: uid,
+                excerpt,
+                citation,
+                excerpt_source,
+                citation_source,
+                submitted_at,
+                images,
+                ..
+            } = pending;
+
+            let author_id = Uuid::from_str(&uid).map_err(|e| QueryError::Deserialize(e.to_string()))?;
+
+            let moved = images
+                .unwrap_or_default()
+                .into_iter()
+                .enumerate()
+                .map(|(order, img)| {
+                    let (mut alt_text, mut transcription) = (img.alt_text, img.transcription);
+
+                    if let Some(edit) = image_edits.iter().find(|e| e.path == img.path) {
+                        if let Some(a) = edit.alt_text.as_deref().and_then(sanitize_alt_text) {
+                            alt_text = a;
+                        }
+
+                        if let Some(t) = edit.transcription.as_deref() {
+                            transcription = sanitize_image_text(t);
+                        }
+                    }
+
+                    image::create_unchecked(
+                        new_id.to_string(),
+                        img.path,
+                        img.width,
+                        img.height,
+                        vec![
+                            image::alt_text::set(alt_text),
+                            image::transcription::set(transcription),
+                            image::order::set(order as i32),
+                        ],
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            client
+                .pending_image()
+                .delete_many(vec![pending_image::post_id::equals(pending_id.clone())])
+                .exec()
+                .await?;
+
+            // Of two final approvals made at once, only one gets to remove the
+            // submission, the other is rolled back instead of publishing again
+            let removed = client
+                .pending_post()
+                .delete_many(vec![
+                    pending_post::category::equals(category),
+                    pending_post::id::equals(pending_id),
+                    pending_post::status::equals(SubmissionStatus::Queued),
+                ])
+                .exec()
+                .await?;
+
+            if removed == 0 {
+                return Err(QueryError::Deserialize("Not Found".to_owned()));
+            }
+
+            create_post(
+                &client,
+                category,
+                new_id,
+                author_id,
+                excerpt,
+                citation,
+                excerpt_source,
+                citation_source,
+                submitted_at,
+                publish_at,
+            )
+            .await?;
+
+            if !moved.is_empty() {
+                client.image().create_many(moved).exec().await?;
+            }
+
+            Ok(Ok(uid))
+        })
+        .await?;
+
+    let uid = match outcome {
+        Ok(uid) => uid,
+        Err(awaiting) => return Ok(awaiting),
+    };
+
+    let url = format!("/posts/{}?id={new_id}", category.to_string().to_ascii_lowercase());
+
+    webhooks::emit(Event::PostConfirmed {
+        id: new_id.to_string(),
+        submission_id: id,
+        section: category,
+        url: url.clone(),
+        publish_at,
+    });
+
+    let notif = NotificationContent::PostApproval {
+        url,
+        comment,
+        publish_at,
+    };
+
+    Ok(Confirmation::Published {
+        post_id: new_id.to_string(),
+        notification: create_notification(uid, &notif).await?,
+    })
+}
+
+/// Whether another reviewer's claim on the submission hasn't run out yet
+pub fn is_locked(pending: &pending_post::Data, reviewer_id: &str) -> bool {
+    let held_by_other = pending.reviewer_id.as_ref().map_or(false, |r| r != reviewer_id);
+    let unexpired = pending.claimed_until.map_or(false, |u| u > Utc::now());
+
+    held_by_other && unexpired
+}
+
+pub async fn get_section_policies() -> Result<Vec<section_policy::Data>, QueryError> {
+    section_policies().await.find_many(vec![]).exec().await
+}
+
+pub async fn set_required_approvals(category: Category, required: i32) -> Result<section_policy::Data, QueryError> {
+    section_policies()
+        .await
+        .upsert(
+            section_policy::UniqueWhereParam::CategoryEquals(category),
+            section_policy::create(category, vec![section_policy::required_approvals::set(required)]),
+            vec![section_policy::required_approvals::set(required)],
+        )
+        .exec()
+        .await
+}
+
+/// Succeeds if the submission is unclaimed, its claim ran out, or the reviewer
+/// already holds it, in which case the claim is renewed
+pub async fn claim_pending_post(id: String, reviewer_id: String) -> Result<bool, QueryError> {
+    let now = Utc::now();
+
+    let claimed = pending_posts()
+        .await
+        .update_many(
+            vec![
+                pending_post::id::equals(id),
+                or![
+                    pending_post::reviewer_id::equals(None),
+                    pending_post::claimed_until::lt(now.into()),
+                    pending_post::reviewer_id::equals(Some(reviewer_id.clone())),
+                ],
+            ],
+            vec![
+                pending_post::reviewer_id::set(Some(reviewer_id)),
+                pending_post::claimed_until::set(Some((now + *REVIEW_CLAIM).into())),
+            ],
+        )
+        .exec()
+        .await?;
+
+    Ok(claimed > 0)
+}
+
+/// Overrides any existing claim and lets the reviewer know
+pub async fn assign_pending_post(
+    id: String,
+    reviewer_id: String,
+    comment: Option<String>,
+) -> Result<pending_post::Data, QueryError> {
+    let pending = pending_posts()
+        .await
+        .update(
+            pending_post::UniqueWhereParam::IdEquals(id),
+            vec![
+                pending_post::reviewer::connect(user::UniqueWhereParam::IdEquals(reviewer_id.clone())),
+                pending_post::claimed_until::set(Some((Utc::now() + *REVIEW_CLAIM).into())),
+            ],
+        )
+        .exec()
+        .await?;
+
+    let url = format!(
+        "/submissions/{}?id={}",
+        pending.category.to_string().to_ascii_lowercase(),
+        pending.id
+    );
+
+    create_notification(reviewer_id, &NotificationContent::SubmissionAssigned { url, comment }).await?;
+
+    Ok(pending)
+}
+
+/// Only releases the reviewer's own claim
+pub async fn release_pending_post(id: String, reviewer_id: String) -> Result<i64, QueryError> {
+    pending_posts()
+        .await
+        .update_many(
+            vec![
+                pending_post::id::equals(id),
+                pending_post::reviewer_id::equals(Some(reviewer_id)),
+            ],
+            vec![
+                pending_post::reviewer_id::set(None),
+                pending_post::claimed_until::set(None),
+            ],
+        )
+        .exec()
+        .await
+}
+
+/// Submissions the reviewer currently holds, the ones expiring soonest first
+pub async fn get_claimed_pending_posts(reviewer_id: String) -> Result<Vec<pending_post::Data>, QueryError> {
+    pending_posts()
+        .await
+        .find_many(vec![
+            pending_post::reviewer_id::equals(Some(reviewer_id)),
+            pending_post::claimed_until::gt(Utc::now().into()),
+        ])
+        .with(pending_post::votes::fetch(vec![]))
+        .order_by(pending_post::claimed_until::order(Direction::Asc))
+        .exec()
+        .await
+}
+
+pub async fn get_user_by_id(id: String) -> Result<Option<user::Data>, QueryError> {
+    users()
+        .await
+        .find_unique(user::UniqueWhereParam::IdEquals(id))
+        .exec()
+        .await
+}
+
+/// Records the rejection and its reasons for [`get_rejection_stats`] while
+/// removing the pending post, all in one transaction so a submission confirmed
+/// or rejected in the meantime isn't rejected again. Fails as `Locked` while
+/// another reviewer holds the claim.
+pub async fn reject_pending_post(
+    category: Category,
+    id: String,
+    reviewer_id: String,
+    reasons: Vec<rejection_reason::Data>,
+    comment: Option<String>,
+) -> Result<Option<notification::Data>, QueryError> {
+    let pending_id = id.clone();
+    let codes = reasons.iter().map(|r| r.code.clone()).collect::<Vec<_>>();
+
+    let pending_post::Data {
+        author_id: uid,
+        excerpt,
+        citation,
+        ..
+    } = PRISMA_CLIENT
+        .get()
+        .await
+        ._transaction()
+        .run(|client| async move {
+            let pending = client
+                .pending_post()
+                .find_first(vec![
+                    pending_post::category::equals(category),
+                    pending_post::id::equals(pending_id.clone()),
+                ])
+                .exec()
+                .await?
+                .ok_or_else(|| QueryError::Deserialize("Not Found".to_owned()))?;
+
+            if is_locked(&pending, &reviewer_id) {
+                return Err(QueryError::Deserialize("Locked".to_owned()));
+            }
+
+            let removed = client
+                .pending_post()
+                .delete_many(vec![
+                    pending_post::category::equals(category),
+                    pending_post::id::equals(pending_id.clone()),
+                    pending_post::status::equals(SubmissionStatus::Queued),
+                ])
+                .exec()
+                .await?;
+
+            if removed == 0 {
+                return Err(QueryError::Deserialize("Not Found".to_owned()));
+            }
+
+            let rejection = client
+                .rejection()
+                .create(
+                    pending_id,
+                    category,
+                    vec![rejection::reviewer::connect(user::UniqueWhereParam::IdEquals(
+                        reviewer_id,
+                    ))],
+                )
+                .exec()
+                .await?;
+
+            if !codes.is_empty() {
+                client
+                    .rejection_reason_link()
+                    .create_many(
+                        codes
+                            .into_iter()
+                            .map(|code| rejection_reason_link::create_unchecked(rejection.id.clone(), code, vec![]))
+                            .collect(),
+                    )
+                    .skip_duplicates()
+                    .exec()
+                    .await?;
+            }
+
+            Ok(pending)
+        })
+        .await?;
+
+    webhooks::emit(Event::PostRejected {
+        submission_id: id,
+        section: category,
+        comment: comment.clone(),
+    });
+
+    let notif = NotificationContent::PostRejection {
+        comment,
+        excerpt,
+        citation,
+        reasons: reasons.into_iter().map(RejectionReasonRef::from).collect(),
+    };
+
+    create_notification(uid, &notif).await
+}
+
+/// Respects the recipient's preferences, so returns [`None`] if they muted
+/// this type of notification. Ones meant for a digest are stored but neither
+/// listed nor streamed until [`send_digests`] bundles them.
+pub async fn create_notification(
+    uid: String,
+    notif: &NotificationContent,
+) -> Result<Option<notification::Data>, QueryError> {
+    let n_type = notif.enum_type();
+
+    let held = match get_notification_delivery(uid.clone(), n_type).await? {
+        NotificationDelivery::Muted => return Ok(None),
+        NotificationDelivery::Digest => n_type != NotificationType::Digest,
+        NotificationDelivery::Immediate => false,
+    };
+
+    let notif = notifications()
+        .await
+        .create(
+            user::UniqueWhereParam::IdEquals(uid),
+            n_type,
+            notif.to_stored(),
+            vec![notification::held::set(held)],
+        )
+        .exec()
+        .await?;
+
+    if !held {
+        hub::publish(notif.clone());
+    }
+
+    Ok(Some(notif))
+}
+
+/// Bundles every held notification into one digest notification per
+/// recipient. Recipients who muted digests keep theirs held.
+pub async fn send_digests() -> Result<(), QueryError> {
+    let held = notifications()
+        .await
+        .find_many(vec![notification::held::equals(true)])
+        .order_by(notification::created_at::order(Direction::Asc))
+        .exec()
+        .await?;
+
+    let mut by_recipient: BTreeMap<String, Vec<notification::Data>> = BTreeMap::new();
+
+    for notif in held {
+        by_recipient.entry(notif.recipient_id.clone()).or_default().push(notif);
+    }
+
+    for (uid, held) in by_recipient {
+        if get_notification_delivery(uid.clone(), NotificationType::Digest).await? == NotificationDelivery::Muted {
+            continue;
+        }
+
+        let ids = held.iter().map(|n| n.id.clone()).collect();
+        let items = held
+            .iter()
+            .filter_map(|n| NotificationContent::from_stored(n.content.as_str()).ok())
+            .collect();
+        let digest = NotificationContent::Digest { items };
+
+        // Together, so a failure can't leave the held ones to be sent again
+        let notif = PRISMA_CLIENT
+            .get()
+            .await
+            ._transaction()
+            .run(|client| async move {
+                let notif = client
+                    .notification()
+                    .create(
+                        user::UniqueWhereParam::IdEquals(uid),
+                        NotificationType::Digest,
+                        digest.to_stored(),
+                        vec![],
+                    )
+                    .exec()
+                    .await?;
+
+                client
+                    .notification()
+                    .delete_many(vec![notification::id::in_vec(ids)])
+                    .exec()
+                    .await?;
+
+                Ok::<_, QueryError>(notif)
+            })
+            .await?;
+
+        hub::publish(notif);
+    }
+
+    Ok(())
+}
+
+/// Users are fetched and notified this many at a time when fanning out
+const FAN_OUT_BATCH_SIZE: i64 = 500;
+
+/// Sends the notification to every user, or only those with the given role,
+/// in batches of [`FAN_OUT_BATCH_SIZE`]. Preferences are respected the same way
+/// as in [`create_notification`]. Returns how many notifications were created.
+pub async fn fan_out_notification(
+    notif: &NotificationContent,
+    role: Option<Role>,
+    expires_at: Option<DateTime<FixedOffset>>,
+) -> Result<i64, QueryError> {
+    let n_type = notif.enum_type();
+    let content = notif.to_stored();
+
+    let mut created = 0;
+    let mut last_id = None;
+
+    loop {
+        let mut filters = vec![];
+
+        if let Some(role) = role {
+            filters.push(user::role::equals(role));
+        }
+
+        if let Some(last_id) = last_id.take() {
+            filters.push(user::id::gt(last_id));
+        }
+
+        let batch = users()
+            .await
+            .find_many(filters)
+            .order_by(user::id::order(Direction::Asc))
+            .take(FAN_OUT_BATCH_SIZE)
+            .exec()
+            .await?;
+
+        let Some(last) = batch.last() else {
+            break;
+        };
+
+        last_id = Some(last.id.clone());
+
+        let uids = batch.iter().map(|u| u.id.clone()).collect::<Vec<_>>();
+
+        let prefs = notification_preferences()
+            .await
+            .find_many(vec![
+                notification_preference::user_id::in_vec(uids.clone()),
+                notification_preference::n_type::equals(n_type),
+            ])
+            .exec()
+            .await?;
+
+        let mut immediate = vec![];
+        let mut rows = vec![];
+
+        for uid in uids {
+            let delivery = prefs
+                .iter()
+                .find(|p| p.user_id == uid)
+                .map_or(NotificationDelivery::Immediate, |p| p.delivery);
+
+            let held = match delivery {
+                NotificationDelivery::Muted => continue,
+                NotificationDelivery::Digest => true,
+                NotificationDelivery::Immediate => false,
+            };
+
+            let id = Uuid::new_v4().to_string();
+
+            if !held {
+                immediate.push(id.clone());
+            }
+
+            rows.push(notification::create_unchecked(
+                uid,
+                n_type,
+                content.clone(),
+                vec![
+                    notification::id::set(id),
+                    notification::held::set(held),
+                    notification::expires_at::set(expires_at),
+                ],
+            ));
+        }
+
+        if !rows.is_empty() {
+            created += notifications().await.create_many(rows).exec().await?;
+        }
+
+        if !immediate.is_empty() {
+            let published = notifications()
+                .await
+                .find_many(vec![notification::id::in_vec(immediate)])
+                .exec()
+                .await?;
+
+            published.into_iter().for_each(hub::publish);
+        }
+
+        if (batch.len() as i64) < FAN_OUT_BATCH_SIZE {
+            break;
+        }
+    }
+
+    Ok(created)
+}
+
+/// Deletes read notifications created before `read_before` and unread ones
+/// created before `unread_before`. Ones held for a digest are left alone.
+pub async fn purge_notifications(
+    read_before: DateTime<FixedOffset>,
+    unread_before: DateTime<FixedOffset>,
+) -> Result<i64, QueryError> {
+    notifications()
+        .await
+        .delete_many(vec![
+            notification::held::equals(false),
+            or![
+                and![
+                    notification::read::equals(true),
+                    notification::created_at::lt(read_before)
+                ],
+                and![
+                    notification::read::equals(false),
+                    notification::created_at::lt(unread_before)
+                ]
+            ],
+        ])
+        .exec()
+        .await
+}
+
+pub async fn delete_expired_notifications() -> Result<i64, QueryError> {
+    notifications()
+        .await
+        .delete_many(vec![notification::expires_at::lt(Utc::now().into())])
+        .exec()
+        .await
+}
+
+pub async fn get_notification_delivery(
+    uid: String,
+    n_type: NotificationType,
+) -> Result<NotificationDelivery, QueryError> {
+    let pref = notification_preferences()
+        .await
+        .find_unique(notification_preference::user_id_n_type(uid, n_type))
+        .exec()
+        .await?;
+
+    Ok(pref.map_or(NotificationDelivery::Immediate, |p| p.delivery))
+}
+
+/// Returns the delivery setting for every notification type, including ones
+/// the user never changed.
+pub async fn get_notification_preferences(
+    uid: String,
+) -> Result<Vec<(NotificationType, NotificationDelivery)>, QueryError> {
+    let prefs = notification_preferences()
+        .await
+        .find_many(vec![notification_preference::user_id::equals(uid)])
+        .exec()
+        .await?;
+
+    Ok(NotificationType::ALL
+        .into_iter()
+        .map(|t| {
+            let delivery = prefs
+                .iter()
+                .find(|p| p.n_type == t)
+                .map_or(NotificationDelivery::Immediate, |p| p.delivery);
+
+            (t, delivery)
+        })
+        .collect())
+}
+
+pub async fn set_notification_preferences(
+    uid: String,
+    prefs: Vec<(NotificationType, NotificationDelivery)>,
+) -> Result<(), QueryError> {
+    let client = PRISMA_CLIENT.get().await;
+
+    let upserts = prefs
+        .into_iter()
+        .map(|(n_type, delivery)| {
+            client.notification_preference().upsert(
+                notification_preference::user_id_n_type(uid.clone(), n_type),
+                notification_preference::create(
+                    user::UniqueWhereParam::IdEquals(uid.clone()),
+                    n_type,
+                    vec![notification_preference::delivery::set(delivery)],
+                ),
+                vec![notification_preference::delivery::set(delivery)],
+            )
+        })
+        .collect::<Vec<_>>();
+
+    client._batch(upserts).await?;
+
+    Ok(())
+}
+
+/// Returns the user's notifications created after the given one, oldest
+/// first. Used for resuming the event stream, so an unknown ID yields nothing.
+pub async fn get_user_notifications_after(user: Uuid, id: String) -> Result<Vec<notification::Data>, QueryError> {
+    let last = notifications()
+        .await
+        .find_first(vec![
+            notification::recipient_id::equals(user.to_string()),
+            notification::id::equals(id),
+        ])
+        .exec()
+        .await?;
+
+    let Some(last) = last else {
+        return Ok(vec![]);
+    };
+
+    notifications()
+        .await
+        .find_many(vec![
+            notification::recipient_id::equals(user.to_string()),
+            notification::held::equals(false),
+            unexpired_notification(),
+            // Notifications created in the same instant are told apart by ID
+            or![
+                notification::created_at::gt(last.created_at),
+                and![
+                    notification::created_at::equals(last.created_at),
+                    notification::id::gt(last.id)
+                ]
+            ],
+        ])
+        .order_by(notification::created_at::order(Direction::Asc))
+        .order_by(notification::id::order(Direction::Asc))
+        .exec()
+        .await
+}
+
+/// Applies every `(id, read)` pair in one transaction, with one query per
+/// distinct `read` value rather than one per notification.
+pub async fn update_user_notifications(uid: String, patches: Vec<(String, bool)>) -> Result<(), QueryError> {
+    let (read, unread): (Vec<_>, Vec<_>) = patches.into_iter().partition(|(_, read)| *read);
+
+    PRISMA_CLIENT
+        .get()
+        .await
+        ._transaction()
+        .run(|client| async move {
+            for (ids, read) in [(read, true), (unread, false)] {
+                if ids.is_empty() {
+                    continue;
+                }
+
+                client
+                    .notification()
+                    .update_many(
+                        vec![
+                            notification::recipient_id::equals(uid.clone()),
+                            notification::id::in_vec(ids.into_iter().map(|(id, _)| id).collect()),
+                        ],
+                        vec![notification::read::set(read)],
+                    )
+                    .exec()
+                    .await?;
+            }
+
+            Ok::<_, QueryError>(())
+        })
+        .await
+}
+
+/// Deletes the given notifications, skipping any whose recipient ID doesn't
+/// match the uid.
+pub async fn delete_user_notifications(uid: String, ids: Vec<String>) -> Result<i64, QueryError> {
+    notifications()
+        .await
+        .delete_many(vec![
+            notification::WhereParam::RecipientId(StringFilter::Equals(uid)),
+            notification::id::in_vec(ids),
+        ])
+        .exec()
+        .await
+}
+
+pub async fn get_notifications_by_id(ids: Vec<String>) -> Result<Vec<notification::Data>, QueryError> {
+    notifications()
+        .await
+        .find_many(vec![notification::id::in_vec(ids)])
+        .exec()
+        .await
+}
+
+pub async fn delete_notifications(ids: Vec<String>) -> Result<i64, QueryError> {
+    notifications()
+        .await
+        .delete_many(vec![notification::id::in_vec(ids)])
+        .exec()
+        .await
+}
+
+/// Bumped whenever [`NotificationContent`] changes in a way older rows can't
+/// be read as
+const NOTIFICATION_CONTENT_VERSION: u32 = 1;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NotificationContent {
+    PostApproval {
+        url: String,
+        comment: Option<String>,
+        /// Set when the post was scheduled rather than published right away
+        #[serde(default)]
+        publish_at: Option<DateTime<FixedOffset>>,
+    },
+    PostRejection {
+        comment: Option<String>,
+        excerpt: String,
+        citation: String,
+        /// Missing from notifications sent before reasons existed
+        #[serde(default)]
+        reasons: Vec<RejectionReasonRef>,
+    },
+    PostDeleted {
+        comment: Option<String>,
+        excerpt: String,
+        citation: String,
+    },
+    PostEdited {
+        url: String,
+        comment: Option<String>,
+    },
+    SubmissionAssigned {
+        url: String,
+        comment: Option<String>,
+    },
+    Announcement {
+        title: String,
+        body: String,
+    },
+    CommentReply {
+        url: String,
+        /// The reply, as sanitized HTML
+        body: String,
+    },
+    ReportResolved {
+        action: ReportAction,
+        /// Left out when the post or comment was deleted
+        url: Option<String>,
+        note: Option<String>,
+    },
+    /// Notifications held back for a user who opted into digests
+    Digest {
+        items: Vec<NotificationContent>,
+    },
+}
+
+impl NotificationContent {
+    pub fn enum_type(&self) -> NotificationType {
+        match self {
+            NotificationContent::PostApproval { .. } => NotificationType::Approval,
+            NotificationContent::PostRejection { .. } => NotificationType::Rejection,
+            NotificationContent::PostDeleted { .. } => NotificationType::PostDeleted,
+            NotificationContent::PostEdited { .. } => NotificationType::PostEdited,
+            NotificationContent::SubmissionAssigned { .. } => NotificationType::SubmissionAssigned,
+            NotificationContent::Announcement { .. } => NotificationType::Announcement,
+            NotificationContent::Digest { .. } => NotificationType::Digest,
+            NotificationContent::CommentReply { .. } => NotificationType::CommentReply,
+            NotificationContent::ReportResolved { .. } => NotificationType::ReportResolved,
+        }
+    }
+
+    /// Serializes to the format stored in the `content` column
+    pub fn to_stored(&self) -> String {
+        serde_json::to_string(&StoredNotificationContent {
+            v: NOTIFICATION_CONTENT_VERSION,
+            content: self,
+        })
+        .unwrap()
+    }
+
+    /// Reads the `content` column, including rows written before it was
+    /// versioned
+    pub fn from_stored(s: &str) -> serde_json::Result<Self> {
+        match serde_json::from_str::<StoredNotificationContent<Self>>(s) {
+            Ok(StoredNotificationContent { content, .. }) => Ok(content),
+            Err(e) => serde_json::from_str::<LegacyNotificationContent>(s)
+                .map(Self::from)
+                .map_err(|_| e),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredNotificationContent<C> {
+    v: u32,
+    #[serde(flatten)]
+    content: C,
+}
+
+/// The untagged format used before [`NOTIFICATION_CONTENT_VERSION`] existed
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LegacyNotificationContent {
+    PostApproval {
+        url: String,
+        comment: Option<String>,
+    },
+    PostRejection {
+        comment: Option<String>,
+        excerpt: String,
+        citation: String,
+    },
+}
+
+impl From<LegacyNotificationContent> for NotificationContent {
+    fn from(value: LegacyNotificationContent) -> Self {
+        match value {
+            LegacyNotificationContent::PostApproval { url, comment } => Self::PostApproval {
+                url,
+                comment,
+                publish_at: None,
+            },
+            LegacyNotificationContent::PostRejection {
+                comment,
+                excerpt,
+                citation,
+            } => Self::PostRejection {
+                comment,
+                excerpt,
+                citation,
+                reasons: vec![],
+            },
+        }
+    }
+}
+
+pub async fn create_job_run(
+    job: &str,
+    started_at: DateTime<FixedOffset>,
+    finished_at: DateTime<FixedOffset>,
+    success: bool,
+    message: String,
+) -> Result<job_run::Data, QueryError> {
+    job_runs()
+        .await
+        .create(
+            job.to_owned(),
+            started_at,
+            success,
+            message,
+            vec![job_run::finished_at::set(Some(finished_at))],
+        )
+        .exec()
+        .await
+}
+
+/// For jobs run on demand, which report their progress with
+/// [`update_job_run`] while they're running
+pub async fn start_job_run(job: &str, message: String) -> Result<job_run::Data, QueryError> {
+    job_runs()
+        .await
+        .create(job.to_owned(), Utc::now().into(), false, message, vec![])
+        .exec()
+        .await
+}
+
+/// Setting `finished_at` marks the run as over
+pub async fn update_job_run(
+    id: String,
+    finished_at: Option<DateTime<FixedOffset>>,
+    success: bool,
+    message: String,
+) -> Result<job_run::Data, QueryError> {
+    job_runs()
+        .await
+        .update(
+            job_run::UniqueWhereParam::IdEquals(id),
+            vec![
+                job_run::finished_at::set(finished_at),
+                job_run::success::set(success),
+                job_run::message::set(message),
+            ],
+        )
+        .exec()
+        .await
+}
+
+pub async fn get_job_runs(job: Option<String>, pagination: PaginationFields) -> Result<Vec<job_run::Data>, QueryError> {
+    let filters = job.into_iter().map(job_run::job::equals).collect();
+
+    job_runs()
+        .await
+        .find_many(filters)
+        .order_by(job_run::started_at::order(Direction::Desc))
+        .skip(pagination.skip())
+        .take(pagination.per_page().into())
+        .exec()
+        .await
+}
+
+/// The job's most recent recorded run
+pub async fn get_last_job_run(job: String) -> Result<Option<job_run::Data>, QueryError> {
+    job_runs()
+        .await
+        .find_first(vec![job_run::job::equals(job)])
+        .order_by(job_run::started_at::order(Direction::Desc))
+        .exec()
+        .await
+}
+
+/// Uploads only count towards the quota for a day
+pub async fn delete_uploads_before(before: DateTime<FixedOffset>) -> Result<i64, QueryError> {
+    uploads()
+        .await
+        .delete_many(vec![upload::created_at::lt(before)])
+        .exec()
+        .await
+}
+
+pub async fn delete_job_runs_before(before: DateTime<FixedOffset>) -> Result<i64, QueryError> {
+    job_runs()
+        .await
+        .delete_many(vec![job_run::started_at::lt(before)])
+        .exec()
+        .await
+}
+
+pub async fn create_webhook(
+    url: String,
+    secret: String,
+    events: Vec<WebhookEvent>,
+) -> Result<webhook::Data, QueryError> {
+    let webhook = webhooks().await.create(url, secret, vec![]).exec().await?;
+
+    webhook_subscriptions()
+        .await
+        .create_many(
+            events
+                .into_iter()
+                .map(|e| webhook_subscription::create_unchecked(webhook.id.clone(), e, vec![]))
+                .collect(),
+        )
+        .skip_duplicates()
+        .exec()
+        .await?;
+
+    get_webhook(webhook.id)
+        .await?
+        .ok_or_else(|| QueryError::Deserialize("Not Found".to_owned()))
+}
+
+pub async fn get_webhook(id: String) -> Result<Option<webhook::Data>, QueryError> {
+    webhooks()
+        .await
+        .find_unique(webhook::id::equals(id))
+        .with(webhook::subscriptions::fetch(vec![]))
+        .exec()
+        .await
+}
+
+pub async fn get_webhooks() -> Result<Vec<webhook::Data>, QueryError> {
+    webhooks()
+        .await
+        .find_many(vec![])
+        .with(webhook::subscriptions::fetch(vec![]))
+        .order_by(webhook::created_at::order(Direction::Asc))
+        .exec()
+        .await
+}
+
+pub async fn remove_webhook(id: String) -> Result<i64, QueryError> {
+    webhooks().await.delete_many(vec![webhook::id::equals(id)]).exec().await
+}
+
+/// Logs a pending delivery for every active webhook subscribed to the event.
+/// Returns the IDs of the new deliveries.
+pub async fn create_webhook_deliveries(event: WebhookEvent, payload: String) -> Result<Vec<String>, QueryError> {
+    let subscribed = webhooks()
+        .await
+        .find_many(vec![
+            webhook::active::equals(true),
+            webhook::subscriptions::some(vec![webhook_subscription::event::equals(event)]),
+        ])
+        .exec()
+        .await?;
+
+    let (ids, deliveries): (Vec<_>, Vec<_>) = subscribed
+        .into_iter()
+        .map(|w| {
+            let id = Uuid::new_v4().to_string();
+            let delivery = webhook_delivery::create_unchecked(
+                w.id,
+                event,
+                payload.clone(),
+                vec![webhook_delivery::id::set(id.clone())],
+            );
+
+            (id, delivery)
+        })
+        .unzip();
+
+    if !deliveries.is_empty() {
+        webhook_deliveries().await.create_many(deliveries).exec().await?;
+    }
+
+    Ok(ids)
+}
+
+/// Pending deliveries that are due, with their webhook fetched
+pub async fn get_due_webhook_deliveries(
+    ids: Option<Vec<String>>,
+    limit: i64,
+) -> Result<Vec<webhook_delivery::Data>, QueryError> {
+    let mut filters = vec![
+        webhook_delivery::status::equals(WebhookDeliveryStatus::Pending),
+        webhook_delivery::next_attempt_at::lte(Utc::now().into()),
+    ];
+
+    if let Some(ids) = ids {
+        filters.push(webhook_delivery::id::in_vec(ids));
+    }
+
+    webhook_deliveries()
+        .await
+        .find_many(filters)
+        .with(webhook_delivery::webhook::fetch())
+        .order_by(webhook_delivery::next_attempt_at::order(Direction::Asc))
+        .take(limit)
+        .exec()
+        .await
+}
+
+/// Pushes the delivery's next attempt back to `lease_until`, unless someone
+/// else already did since it was fetched. Returns whether it was claimed.
+pub async fn claim_webhook_delivery(
+    delivery: &webhook_delivery::Data,
+    lease_until: DateTime<FixedOffset>,
+) -> Result<bool, QueryError> {
+    let claimed = webhook_deliveries()
+        .await
+        .update_many(
+            vec![
+                webhook_delivery::id::equals(delivery.id.clone()),
+                webhook_delivery::status::equals(WebhookDeliveryStatus::Pending),
+                webhook_delivery::attempts::equals(delivery.attempts),
+                webhook_delivery::next_attempt_at::equals(delivery.next_attempt_at),
+            ],
+            vec![webhook_delivery::next_attempt_at::set(lease_until)],
+        )
+        .exec()
+        .await?;
+
+    Ok(claimed == 1)
+}
+
+pub async fn update_webhook_delivery(id: String, params: Vec<webhook_delivery::SetParam>) -> Result<(), QueryError> {
+    webhook_deliveries()
+        .await
+        .update(webhook_delivery::id::equals(id), params)
+        .exec()
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_webhook_deliveries(
+    webhook_id: Option<String>,
+    status: Option<WebhookDeliveryStatus>,
+    pagination: PaginationFields,
+) -> Result<Vec<webhook_delivery::Data>, QueryError> {
+    let mut filters = vec![];
+
+    if let Some(webhook_id) = webhook_id {
+        filters.push(webhook_delivery::webhook_id::equals(webhook_id));
+    }
+
+    if let Some(status) = status {
+        filters.push(webhook_delivery::status::equals(status));
+    }
+
+    webhook_deliveries()
+        .await
+        .find_many(filters)
+        .order_by(webhook_delivery::created_at::order(Direction::Desc))
+        .skip(pagination.skip())
+        .take(pagination.per_page().into())
+        .exec()
+        .await
+}
+
+pub async fn create_collection(owner_id: String, name: String, public: bool) -> Result<collection::Data, QueryError> {
+    collections()
+        .await
+        .create(
+            user::UniqueWhereParam::IdEquals(owner_id),
+            name,
+            vec![collection::public::set(public)],
+        )
+        .exec()
+        .await
+}
+
+/// Newest first, without their bookmarks
+pub async fn get_user_collections(owner_id: String) -> Result<Vec<collection::Data>, QueryError> {
+    collections()
+        .await
+        .find_many(vec![collection::owner_id::equals(owner_id)])
+        .order_by(collection::created_at::order(Direction::Desc))
+        .exec()
+        .await
+}
+
+/// Fetches the collection's bookmarks in order, along with their posts. Posts
+/// that are still scheduled are left out until they're published.
+pub async fn get_collection(id: String) -> Result<Option<collection::Data>, QueryError> {
+    collections()
+        .await
+        .find_unique(collection::UniqueWhereParam::IdEquals(id))
+        .with(
+            collection::bookmarks::fetch(vec![bookmark::post::is(vec![post::confirmed_at::lte(
+                Utc::now().into(),
+            )])])
+            .order_by(bookmark::order::order(Direction::Asc))
+            .with(
+                bookmark::post::fetch()
+                    .with(post::images::fetch(vec![]).order_by(image::order::order(Direction::Asc)))
+                    .with(post::reaction_counts::fetch(vec![])),
+            ),
+        )
+        .exec()
+        .await
+}
+
+/// Returns the number of updated rows, 0 if the user doesn't own the collection
+pub async fn update_collection(
+    owner_id: String,
+    id: String,
+    params: Vec<collection::SetParam>,
+) -> Result<i64, QueryError> {
+    collections()
+        .await
+        .update_many(
+            vec![collection::id::equals(id), collection::owner_id::equals(owner_id)],
+            params,
+        )
+        .exec()
+        .await
+}
+
+/// Returns the number of deleted rows, 0 if the user doesn't own the collection
+pub async fn remove_collection(owner_id: String, id: String) -> Result<i64, QueryError> {
+    collections()
+        .await
+        .delete_many(vec![collection::id::equals(id), collection::owner_id::equals(owner_id)])
+        .exec()
+        .await
+}
+
+/// Adds the post to the end of the collection, does nothing if it's already in
+/// there
+pub async fn add_bookmark(collection_id: String, post_id: String) -> Result<bookmark::Data, QueryError> {
+    let last = bookmarks()
+        .await
+        .find_first(vec![bookmark::collection_id::equals(collection_id.clone())])
+        .order_by(bookmark::order::order(Direction::Desc))
+        .exec()
+        .await?;
+
+    let order = last.map_or(0, |b| b.order + 1);
+
+    bookmarks()
+        .await
+        .upsert(
+            bookmark::collection_id_post_id(collection_id.clone(), post_id.clone()),
+            bookmark::create(
+                collection::UniqueWhereParam::IdEquals(collection_id),
+                post::UniqueWhereParam::IdEquals(post_id),
+                vec![bookmark::order::set(order)],
+            ),
+            vec![],
+        )
+        .exec()
+        .await
+}
+
+/// Returns the number of deleted rows
+pub async fn remove_bookmark(collection_id: String, post_id: String) -> Result<i64, QueryError> {
+    bookmarks()
+        .await
+        .delete_many(vec![
+            bookmark::collection_id::equals(collection_id),
+            bookmark::post_id::equals(post_id),
+        ])
+        .exec()
+        .await
+}
+
+/// Puts the bookmarks in the order of `post_ids`, which has to contain every
+/// post in the collection
+pub async fn reorder_bookmarks(collection_id: String, post_ids: Vec<String>) -> Result<(), QueryError> {
+    let client = PRISMA_CLIENT.get().await;
+
+    let updates = post_ids
+        .into_iter()
+        .enumerate()
+        .map(|(i, post_id)| {
+            client.bookmark().update(
+                bookmark::collection_id_post_id(collection_id.clone(), post_id),
+                vec![bookmark::order::set(i as i32)],
+            )
+        })
+        .collect::<Vec<_>>();
+
+    client._batch(updates).await?;
+
+    Ok(())
+}
+
+/// Sets the user's reaction to the post, replacing the one they had before
+pub async fn set_reaction(user_id: String, post_id: String, kind: ReactionKind) -> Result<(), QueryError> {
+    PRISMA_CLIENT
+        .get()
+        .await
+        ._transaction()
+        .run(|client| async move {
+            let previous = client
+                .reaction()
+                .find_unique(reaction::user_id_post_id(user_id.clone(), post_id.clone()))
+                .exec()
+                .await?;
+
+            match previous.map(|r| r.kind) {
+                Some(previous) if previous == kind => return Ok(()),
+                Some(previous) => {
+                    client
+                        .reaction()
+                        .update(
+                            reaction::user_id_post_id(user_id, post_id.clone()),
+                            vec![reaction::kind::set(kind)],
+                        )
+                        .exec()
+                        .await?;
+
+                    client
+                        .reaction_count()
+                        .update(
+                            reaction_count::post_id_kind(post_id.clone(), previous),
+                            vec![reaction_count::count::decrement(1)],
+                        )
+                        .exec()
+                        .await?;
+                }
+                None => {
+                    client
+                        .reaction()
+                        .create(
+                            user::UniqueWhereParam::IdEquals(user_id),
+                            post::UniqueWhereParam::IdEquals(post_id.clone()),
+                            kind,
+                            vec![],
+                        )
+                        .exec()
+                        .await?;
+
+                    client
+                        .post()
+                        .update(
+                            post::UniqueWhereParam::IdEquals(post_id.clone()),
+                            vec![post::reaction_count::increment(1)],
+                        )
+                        .exec()
+                        .await?;
+                }
+            }
+
+            client
+                .reaction_count()
+                .upsert(
+                    reaction_count::post_id_kind(post_id.clone(), kind),
+                    reaction_count::create(
+                        post::UniqueWhereParam::IdEquals(post_id),
+                        kind,
+                        vec![reaction_count::count::set(1)],
+                    ),
+                    vec![reaction_count::count::increment(1)],
+                )
+                .exec()
+                .await?;
+
+            Ok::<_, QueryError>(())
+        })
+        .await
+}
+
+/// Returns whether the user had reacted to the post
+pub async fn remove_reaction(user_id: String, post_id: String) -> Result<bool, QueryError> {
+    PRISMA_CLIENT
+        .get()
+        .await
+        ._transaction()
+        .run(|client| async move {
+            let previous = client
+                .reaction()
+                .find_unique(reaction::user_id_post_id(user_id.clone(), post_id.clone()))
+                .exec()
+                .await?;
+
+            let Some(previous) = previous else {
+                return Ok(false);
+            };
+
+            client
+                .reaction()
+                .delete(reaction::user_id_post_id(user_id, post_id.clone()))
+                .exec()
+                .await?;
+
+            client
+                .reaction_count()
+                .update(
+                    reaction_count::post_id_kind(post_id.clone(), previous.kind),
+                    vec![reaction_count::count::decrement(1)],
+                )
+                .exec()
+                .await?;
+
+            client
+                .post()
+                .update(
+                    post::UniqueWhereParam::IdEquals(post_id),
+                    vec![post::reaction_count::decrement(1)],
+                )
+                .exec()
+                .await?;
+
+            Ok::<_, QueryError>(true)
+        })
+        .await
+}
+
+/// Counts a view of the post, unless the viewer already viewed it today.
+/// Returns whether it was counted.
+pub async fn record_post_view(post_id: String, viewer: String) -> Result<bool, QueryError> {
+    let today: DateTime<FixedOffset> = DateTime::<Utc>::from_utc(
+        Utc::now()
+            .date_naive()
+            .and_hms_opt(0, 0, 0)
+            .expect("midnight is a valid time"),
+        Utc,
+    )
+    .into();
+
+    let created = post_views()
+        .await
+        .create_many(vec![post_view::create_unchecked(
+            post_id.clone(),
+            viewer,
+            today,
+            vec![],
+        )])
+        .skip_duplicates()
+        .exec()
+        .await?;
+
+    if created == 0 {
+        return Ok(false);
+    }
+
+    posts()
+        .await
+        .update(
+            post::UniqueWhereParam::IdEquals(post_id),
+            vec![post::view_count::increment(1)],
+        )
+        .exec()
+        .await?;
+
+    Ok(true)
+}
+
+/// How many views a reaction is worth towards a post's popularity
+const REACTION_WEIGHT: f64 = 5.0;
+/// How quickly popularity falls off as posts get older, higher is faster
+const POPULARITY_GRAVITY: f64 = 1.5;
+const POPULARITY_BATCH_SIZE: i64 = 500;
+
+/// Recomputes the popularity of every post, returns how many there were
+pub async fn update_post_popularity() -> Result<usize, QueryError> {
+    let client = PRISMA_CLIENT.get().await;
+    let now = Utc::now();
+
+    let mut after: Option<String> = None;
+    let mut updated = 0;
+
+    loop {
+        let batch = client
+            .post()
+            .find_many(after.take().into_iter().map(post::id::gt).collect())
+            .select(post::select!({ id confirmed_at view_count reaction_count }))
+            .order_by(post::id::order(Direction::Asc))
+            .take(POPULARITY_BATCH_SIZE)
+            .exec()
+            .await?;
+
+        let Some(last) = batch.last() else {
+            break;
+        };
+
+        after = Some(last.id.clone());
+
+        let full = batch.len() as i64 == POPULARITY_BATCH_SIZE;
+        let updates = batch
+            .into_iter()
+            .map(|p| {
+                let age_hours = (now - p.confirmed_at.with_timezone(&Utc)).num_minutes().max(0) as f64 / 60.0;
+                let score = (f64::from(p.reaction_count) * REACTION_WEIGHT + f64::from(p.view_count))
+                    / (age_hours + 2.0).powf(POPULARITY_GRAVITY);
+
+                client.post().update(
+                    post::UniqueWhereParam::IdEquals(p.id),
+                    vec![post::popularity::set(score)],
+                )
+            })
+            .collect::<Vec<_>>();
+
+        updated += updates.len();
+        client._batch(updates).await?;
+
+        if !full {
+            break;
+        }
+    }
+
+    Ok(updated)
+}
+
+/// Held for moderation if the author's account is new, the parent's author is
+/// notified otherwise
+pub async fn create_comment(
+    post_id: String,
+    author_id: String,
+    parent_id: Option<String>,
+    body: String,
+) -> Result<comment::Data, QueryError> {
+    let author = users()
+        .await
+        .find_unique(user::UniqueWhereParam::IdEquals(author_id.clone()))
+        .exec()
+        .await?;
+
+    let held = author.map_or(true, |a| {
+        a.role != Role::Admin && a.created_at.map_or(false, |t| t > Utc::now() - *NEW_ACCOUNT_HOLD)
+    });
+
+    let mut params = vec![comment::status::set(if held {
+        CommentStatus::Held
+    } else {
+        CommentStatus::Visible
+    })];
+
+    if let Some(parent_id) = parent_id {
+        params.push(comment::parent::connect(comment::UniqueWhereParam::IdEquals(parent_id)));
+    }
+
+    let comment = comments()
+        .await
+        .create(
+            post::UniqueWhereParam::IdEquals(post_id),
+            user::UniqueWhereParam::IdEquals(author_id),
+            body,
+            params,
+        )
+        .exec()
+        .await?;
+
+    if !held {
+        notify_comment_reply(&comment).await?;
+    }
+
+    Ok(comment)
+}
+
+/// Lets the parent comment's author know about the reply, unless they're the
+/// one who wrote it
+async fn notify_comment_reply(reply: &comment::Data) -> Result<(), QueryError> {
+    let Some(parent_id) = reply.parent_id.clone() else {
+        return Ok(());
+    };
+
+    let parent = comments()
+        .await
+        .find_unique(comment::UniqueWhereParam::IdEquals(parent_id))
+        .exec()
+        .await?;
+
+    let Some(parent) = parent.filter(|p| p.author_id != reply.author_id && p.status != CommentStatus::Removed) else {
+        return Ok(());
+    };
+
+    let Some(post) = get_post_by_id(reply.post_id.clone()).await? else {
+        return Ok(());
+    };
+
+    let url = format!(
+        "/posts/{}?id={}#comment-{}",
+        post.category.to_string().to_ascii_lowercase(),
+        post.id,
+        reply.id
+    );
+
+    create_notification(
+        parent.author_id,
+        &NotificationContent::CommentReply {
+            url,
+            body: reply.body.clone(),
+        },
+    )
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_comment(id: String) -> Result<Option<comment::Data>, QueryError> {
+    comments()
+        .await
+        .find_unique(comment::UniqueWhereParam::IdEquals(id))
+        .exec()
+        .await
+}
+
+/// Oldest first, including removed comments so replies to them can still be
+/// threaded. Held ones are left out.
+pub async fn get_post_comments(
+    post_id: String,
+    pagination: PaginationFields,
+) -> Result<Vec<comment::Data>, QueryError> {
+    comments()
+        .await
+        .find_many(vec![
+            comment::post_id::equals(post_id),
+            comment::status::in_vec(vec![CommentStatus::Visible, CommentStatus::Removed]),
+        ])
+        .order_by(comment::created_at::order(Direction::Asc))
+        .skip(pagination.skip())
+        .take(pagination.per_page().into())
+        .exec()
+        .await
+}
+
+pub async fn update_comment_body(id: String, body: String) -> Result<comment::Data, QueryError> {
+    comments()
+        .await
+        .update(
+            comment::UniqueWhereParam::IdEquals(id),
+            vec![
+                comment::body::set(body),
+                comment::edited_at::set(Some(Utc::now().into())),
+            ],
+        )
+        .exec()
+        .await
+}
+
+/// Empties the comment instead of deleting it, so its replies stay threaded
+pub async fn remove_comment(id: String) -> Result<comment::Data, QueryError> {
+    comments()
+        .await
+        .update(
+            comment::UniqueWhereParam::IdEquals(id),
+            vec![
+                comment::body::set(String::new()),
+                comment::status::set(CommentStatus::Removed),
+            ],
+        )
+        .exec()
+        .await
+}
+
+/// The moderation queue, oldest first
+pub async fn get_held_comments(pagination: PaginationFields) -> Result<Vec<comment::Data>, QueryError> {
+    comments()
+        .await
+        .find_many(vec![comment::status::equals(CommentStatus::Held)])
+        .order_by(comment::created_at::order(Direction::Asc))
+        .skip(pagination.skip())
+        .take(pagination.per_page().into())
+        .exec()
+        .await
+}
+
+/// Approves or removes a held comment, returns [`None`] if it isn't held.
+/// Approved replies notify the parent's author like any other.
+pub async fn moderate_comment(id: String, approve: bool) -> Result<Option<comment::Data>, QueryError> {
+    let params = if approve {
+        vec![comment::status::set(CommentStatus::Visible)]
+    } else {
+        vec![
+            comment::status::set(CommentStatus::Removed),
+            comment::body::set(String::new()),
+        ]
+    };
+
+    let updated = comments()
+        .await
+        .update_many(
+            vec![
+                comment::id::equals(id.clone()),
+                comment::status::equals(CommentStatus::Held),
+            ],
+            params,
+        )
+        .exec()
+        .await?;
+
+    if updated == 0 {
+        return Ok(None);
+    }
+
+    let comment = get_comment(id).await?;
+
+    if let Some(comment) = comment.as_ref().filter(|_| approve) {
+        notify_comment_reply(comment).await?;
+    }
+
+    Ok(comment)
+}
+
+/// What a report is about
+#[derive(Clone, PartialEq, Eq)]
+pub enum ReportTarget {
+    Post(String),
+    Comment(String),
+}
+
+impl ReportTarget {
+    /// Unique among open reports
+    fn key(&self) -> String {
+        match self {
+            ReportTarget::Post(id) => format!("post:{id}"),
+            ReportTarget::Comment(id) => format!("comment:{id}"),
+        }
+    }
+}
+
+/// Groups the filing with any open report of the same target. Filing again
+/// replaces the user's earlier reason and details.
+pub async fn file_report(
+    reporter_id: String,
+    target: ReportTarget,
+    reason: ReportReason,
+    details: Option<String>,
+) -> Result<report::Data, QueryError> {
+    let key = target.key();
+
+    let link = match target {
+        ReportTarget::Post(id) => report::post::connect(post::UniqueWhereParam::IdEquals(id)),
+        ReportTarget::Comment(id) => report::comment::connect(comment::UniqueWhereParam::IdEquals(id)),
+    };
+
+    let report = reports()
+        .await
+        .upsert(
+            report::UniqueWhereParam::OpenTargetEquals(key.clone()),
+            report::create(vec![report::open_target::set(Some(key)), link]),
+            vec![],
+        )
+        .exec()
+        .await?;
+
+    report_filings()
+        .await
+        .upsert(
+            report_filing::report_id_reporter_id(report.id.clone(), reporter_id.clone()),
+            report_filing::create(
+                report::UniqueWhereParam::IdEquals(report.id.clone()),
+                user::UniqueWhereParam::IdEquals(reporter_id),
+                reason,
+                vec![report_filing::details::set(details.clone())],
+            ),
+            vec![
+                report_filing::reason::set(reason),
+                report_filing::details::set(details),
+                report_filing::created_at::set(Utc::now().into()),
+            ],
+        )
+        .exec()
+        .await?;
+
+    Ok(report)
+}
+
+/// Fetches the filings along with it
+pub async fn get_report(id: String) -> Result<Option<report::Data>, QueryError> {
+    reports()
+        .await
+        .find_unique(report::UniqueWhereParam::IdEquals(id))
+        .with(report::filings::fetch(vec![]).order_by(report_filing::created_at::order(Direction::Asc)))
+        .exec()
+        .await
+}
 
-pub async fn create_notification(uid: String, notif: &NotificationContent) -> Result<notification::Data, QueryError> {
-    let content = serde_json::to_string(notif).unwrap();
+/// Open reports oldest first, resolved ones most recently resolved first
+pub async fn get_reports(resolved: bool, pagination: PaginationFields) -> Result<Vec<report::Data>, QueryError> {
+    let (filter, order) = if resolved {
+        (
+            report::resolved_at::not(None),
+            report::resolved_at::order(Direction::Desc),
+        )
+    } else {
+        (
+            report::resolved_at::equals(None),
+            report::created_at::order(Direction::Asc),
+        )
+    };
 
-    notifications()
+    reports()
         .await
-        .create(
-            user::UniqueWhereParam::IdEquals(uid),
-            notif.enum_type(),
-            content,
-            vec![],
-        )
+        .find_many(vec![filter])
+        .with(report::filings::fetch(vec![]).order_by(report_filing::created_at::order(Direction::Asc)))
+        .order_by(order)
+        .skip(pagination.skip())
+        .take(pagination.per_page().into())
         .exec()
         .await
 }
 
-pub async fn update_user_notification(uid: String, id: String, read: bool) -> Result<(), QueryError> {
-    notifications()
+/// Closes the report before its action is carried out, so only one admin gets
+/// to act on it. Returns whether it was still open.
+pub async fn claim_report(
+    id: String,
+    resolver_id: String,
+    action: ReportAction,
+    note: Option<String>,
+) -> Result<bool, QueryError> {
+    let claimed = reports()
+        .await
+        .update_many(
+            vec![report::id::equals(id), report::resolved_at::equals(None)],
+            vec![
+                report::open_target::set(None),
+                report::action::set(Some(action)),
+                report::note::set(note),
+                report::resolved_at::set(Some(Utc::now().into())),
+                report::resolver_id::set(Some(resolver_id)),
+            ],
+        )
+        .exec()
+        .await?;
+
+    Ok(claimed == 1)
+}
+
+/// Undoes [`claim_report`] when the action couldn't be carried out. Takes the
+/// report as it was before it was claimed.
+pub async fn reopen_report(report: &report::Data) -> Result<(), QueryError> {
+    reports()
         .await
         .update_many(
+            vec![report::id::equals(report.id.clone())],
             vec![
-                notification::WhereParam::RecipientId(StringFilter::Equals(uid)),
-                notification::WhereParam::Id(StringFilter::Equals(id)),
+                report::open_target::set(report.open_target.clone()),
+                report::action::set(None),
+                report::note::set(None),
+                report::resolved_at::set(None),
+                report::resolver_id::set(None),
             ],
-            vec![notification::SetParam::SetRead(read)],
         )
         .exec()
         .await?;
@@ -395,51 +3188,696 @@ pub async fn update_user_notification(uid: String, id: String, read: bool) -> Re
     Ok(())
 }
 
-/// Deletes the given notification only if the recipient ID matches the uid.
-pub async fn delete_user_notification(uid: String, id: String) -> Result<(), QueryError> {
-    notifications()
+/// Notifies everyone who filed the report once its action is done, see
+/// [`claim_report`]
+pub async fn finish_report(id: String, url: Option<String>) -> Result<Option<report::Data>, QueryError> {
+    let Some(report) = get_report(id).await? else {
+        return Ok(None);
+    };
+
+    let Some(action) = report.action else {
+        return Ok(None);
+    };
+
+    let notif = NotificationContent::ReportResolved {
+        action,
+        url,
+        note: report.note.clone(),
+    };
+
+    for filing in report.filings.iter().flatten() {
+        create_notification(filing.reporter_id.clone(), &notif).await?;
+    }
+
+    Ok(Some(report))
+}
+
+/// One moderation action, see [`record_audit_event`]
+pub struct AuditEntry {
+    pub actor_id: String,
+    pub action: AuditAction,
+    pub target_id: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// Audit events are only ever appended, there's intentionally no way to
+/// update or delete them
+pub async fn record_audit_event(entry: AuditEntry) -> Result<audit_event::Data, QueryError> {
+    audit_events()
         .await
-        // Will only delete one due to the unique constraint, delete_many method is used due to
-        // method limitations
-        .delete_many(vec![
-            notification::WhereParam::RecipientId(StringFilter::Equals(uid)),
-            notification::WhereParam::Id(StringFilter::Equals(id)),
+        .create(
+            entry.action,
+            entry.target_id,
+            vec![
+                audit_event::actor::connect(user::UniqueWhereParam::IdEquals(entry.actor_id)),
+                audit_event::before::set(entry.before),
+                audit_event::after::set(entry.after),
+                audit_event::ip::set(entry.ip),
+                audit_event::user_agent::set(entry.user_agent),
+            ],
+        )
+        .exec()
+        .await
+}
+
+#[derive(Clone, Default)]
+pub struct AuditFilter {
+    pub actor_id: Option<String>,
+    pub action: Option<AuditAction>,
+    pub target_id: Option<String>,
+    /// Inclusive
+    pub since: Option<DateTime<FixedOffset>>,
+    /// Exclusive
+    pub until: Option<DateTime<FixedOffset>>,
+}
+
+impl AuditFilter {
+    fn params(self) -> Vec<audit_event::WhereParam> {
+        let mut params = vec![];
+
+        params.extend(self.actor_id.map(|a| audit_event::actor_id::equals(Some(a))));
+        params.extend(self.action.map(audit_event::action::equals));
+        params.extend(self.target_id.map(audit_event::target_id::equals));
+        params.extend(self.since.map(audit_event::created_at::gte));
+        params.extend(self.until.map(audit_event::created_at::lt));
+
+        params
+    }
+}
+
+/// Newest first
+pub async fn get_audit_events(
+    filter: AuditFilter,
+    pagination: PaginationFields,
+) -> Result<Vec<audit_event::Data>, QueryError> {
+    audit_events()
+        .await
+        .find_many(filter.params())
+        .order_by(audit_event::created_at::order(Direction::Desc))
+        .skip(pagination.skip())
+        .take(pagination.per_page().into())
+        .exec()
+        .await
+}
+
+/// Most events an export can contain
+const AUDIT_EXPORT_LIMIT: usize = 50_000;
+const AUDIT_EXPORT_BATCH: usize = 1000;
+
+/// Every matching event oldest first, capped at [`AUDIT_EXPORT_LIMIT`]. Since
+/// events are only appended, paging with offsets doesn't skip any.
+pub async fn export_audit_events(filter: AuditFilter) -> Result<Vec<audit_event::Data>, QueryError> {
+    let mut events: Vec<audit_event::Data> = vec![];
+
+    while events.len() < AUDIT_EXPORT_LIMIT {
+        let mut filters = filter.clone().params();
+
+        // Continues after the last event rather than skipping, so events
+        // logged in the meantime can't shift the batches. The ID breaks ties
+        // between events with the same timestamp.
+        if let Some(last) = events.last() {
+            filters.push(or![
+                audit_event::created_at::gt(last.created_at),
+                and![
+                    audit_event::created_at::equals(last.created_at),
+                    audit_event::id::gt(last.id.clone())
+                ]
+            ]);
+        }
+
+        let batch = audit_events()
+            .await
+            .find_many(filters)
+            .order_by(audit_event::created_at::order(Direction::Asc))
+            .order_by(audit_event::id::order(Direction::Asc))
+            .take(AUDIT_EXPORT_BATCH as i64)
+            .exec()
+            .await?;
+        let done = batch.len() < AUDIT_EXPORT_BATCH;
+
+        events.extend(batch);
+
+        if done {
+            break;
+        }
+    }
+
+    events.truncate(AUDIT_EXPORT_LIMIT);
+
+    Ok(events)
+}
+
+/// A rejection reason as it's sent along with notifications
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RejectionReasonRef {
+    pub code: String,
+    pub label: String,
+}
+
+impl From<rejection_reason::Data> for RejectionReasonRef {
+    fn from(value: rejection_reason::Data) -> Self {
+        Self {
+            code: value.code,
+            label: value.label,
+        }
+    }
+}
+
+/// Archived ones last
+pub async fn get_rejection_reasons() -> Result<Vec<rejection_reason::Data>, QueryError> {
+    rejection_reasons()
+        .await
+        .find_many(vec![])
+        .order_by(rejection_reason::archived::order(Direction::Asc))
+        .order_by(rejection_reason::label::order(Direction::Asc))
+        .exec()
+        .await
+}
+
+/// Only returns reasons that exist and aren't archived, in the order given
+pub async fn get_active_rejection_reasons(codes: Vec<String>) -> Result<Vec<rejection_reason::Data>, QueryError> {
+    let reasons = rejection_reasons()
+        .await
+        .find_many(vec![
+            rejection_reason::code::in_vec(codes.clone()),
+            rejection_reason::archived::equals(false),
         ])
         .exec()
         .await?;
 
-    Ok(())
+    Ok(codes
+        .iter()
+        .filter_map(|c| reasons.iter().find(|r| &r.code == c).cloned())
+        .collect())
 }
 
-pub async fn delete_notification(id: String) -> Result<(), QueryError> {
-    notifications()
+pub async fn create_rejection_reason(
+    code: String,
+    label: String,
+    description: Option<String>,
+) -> Result<rejection_reason::Data, QueryError> {
+    rejection_reasons()
+        .await
+        .create(code, label, vec![rejection_reason::description::set(description)])
+        .exec()
+        .await
+}
+
+/// Returns [`None`] if there's no reason with the code
+pub async fn update_rejection_reason(
+    code: String,
+    params: Vec<rejection_reason::SetParam>,
+) -> Result<Option<rejection_reason::Data>, QueryError> {
+    let updated = rejection_reasons()
         .await
-        .delete(notification::UniqueWhereParam::IdEquals(id))
+        .update_many(vec![rejection_reason::code::equals(code.clone())], params)
         .exec()
         .await?;
 
-    Ok(())
+    if updated == 0 {
+        return Ok(None);
+    }
+
+    rejection_reasons()
+        .await
+        .find_unique(rejection_reason::UniqueWhereParam::CodeEquals(code))
+        .exec()
+        .await
 }
 
-#[derive(Serialize, Deserialize)]
-#[serde(untagged)]
-pub enum NotificationContent {
-    PostApproval {
-        url: String,
-        comment: Option<String>,
-    },
-    PostRejection {
-        comment: Option<String>,
-        excerpt: String,
-        citation: String,
-    },
+pub async fn get_canned_responses() -> Result<Vec<canned_response::Data>, QueryError> {
+    canned_responses()
+        .await
+        .find_many(vec![])
+        .order_by(canned_response::name::order(Direction::Asc))
+        .exec()
+        .await
 }
 
-impl NotificationContent {
-    pub fn enum_type(&self) -> NotificationType {
-        match self {
-            NotificationContent::PostApproval { .. } => NotificationType::Approval,
-            NotificationContent::PostRejection { .. } => NotificationType::Rejection,
+pub async fn get_canned_response(id: String) -> Result<Option<canned_response::Data>, QueryError> {
+    canned_responses()
+        .await
+        .find_unique(canned_response::UniqueWhereParam::IdEquals(id))
+        .exec()
+        .await
+}
+
+pub async fn create_canned_response(name: String, body: String) -> Result<canned_response::Data, QueryError> {
+    canned_responses().await.create(name, body, vec![]).exec().await
+}
+
+/// Returns [`None`] if there's no response with the ID
+pub async fn update_canned_response(
+    id: String,
+    params: Vec<canned_response::SetParam>,
+) -> Result<Option<canned_response::Data>, QueryError> {
+    let updated = canned_responses()
+        .await
+        .update_many(vec![canned_response::id::equals(id.clone())], params)
+        .exec()
+        .await?;
+
+    if updated == 0 {
+        return Ok(None);
+    }
+
+    get_canned_response(id).await
+}
+
+pub async fn remove_canned_response(id: String) -> Result<i64, QueryError> {
+    canned_responses()
+        .await
+        .delete_many(vec![canned_response::id::equals(id)])
+        .exec()
+        .await
+}
+
+/// How many rejections there were in total, per reason and per section. A
+/// rejection with several reasons counts towards each of them.
+#[derive(Serialize)]
+pub struct RejectionStats {
+    pub total: i64,
+    pub by_reason: Vec<ReasonCount>,
+    pub by_section: Vec<SectionCount>,
+}
+
+#[derive(Serialize)]
+pub struct ReasonCount {
+    pub code: String,
+    pub label: String,
+    pub count: i64,
+    /// The same count split up by section
+    pub by_section: Vec<SectionCount>,
+}
+
+#[derive(Serialize)]
+pub struct SectionCount {
+    pub section: Category,
+    pub count: i64,
+}
+
+/// A row of the grouped counts below, `reason_code` is only selected when
+/// counting links
+#[derive(Deserialize)]
+struct GroupedCount {
+    category: Category,
+    #[serde(rename = "reasonCode", default)]
+    reason_code: Option<String>,
+    count: i64,
+}
+
+/// Counts are grouped in the database, so this is two queries however many
+/// reasons and rejections there are
+pub async fn get_rejection_stats(
+    since: Option<DateTime<FixedOffset>>,
+    until: Option<DateTime<FixedOffset>>,
+) -> Result<RejectionStats, QueryError> {
+    let mut range = vec![];
+    let mut params = vec![];
+
+    if let Some(since) = since {
+        range.push("r.createdAt >= ?");
+        params.push(PrismaValue::DateTime(since));
+    }
+
+    if let Some(until) = until {
+        range.push("r.createdAt < ?");
+        params.push(PrismaValue::DateTime(until));
+    }
+
+    let range = if range.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", range.join(" AND "))
+    };
+    let client = PRISMA_CLIENT.get().await;
+
+    let sections = client
+        ._query_raw::<GroupedCount>(Raw::new(
+            &format!("SELECT r.category, COUNT(*) AS count FROM Rejection r {range} GROUP BY r.category"),
+            params.clone(),
+        ))
+        .exec()
+        .await?;
+    let links = client
+        ._query_raw::<GroupedCount>(Raw::new(
+            &format!(
+                "SELECT l.reasonCode, r.category, COUNT(*) AS count FROM RejectionReasonLink l \
+                 JOIN Rejection r ON r.id = l.rejectionId {range} GROUP BY l.reasonCode, r.category"
+            ),
+            params,
+        ))
+        .exec()
+        .await?;
+
+    let count_in = |rows: &[GroupedCount], code: Option<&str>| {
+        Category::ALL
+            .into_iter()
+            .map(|section| SectionCount {
+                section,
+                count: rows
+                    .iter()
+                    .filter(|r| r.category == section && r.reason_code.as_deref() == code)
+                    .map(|r| r.count)
+                    .sum(),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let by_section = count_in(&sections, None);
+    let mut by_reason = get_rejection_reasons()
+        .await?
+        .into_iter()
+        .map(|reason| {
+            let sections = count_in(&links, Some(&reason.code));
+
+            ReasonCount {
+                count: sections.iter().map(|s| s.count).sum(),
+                code: reason.code,
+                label: reason.label,
+                by_section: sections,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    by_reason.sort_by_key(|r| Reverse(r.count));
+
+    Ok(RejectionStats {
+        total: by_section.iter().map(|s| s.count).sum(),
+        by_reason,
+        by_section,
+    })
+}
+
+/// Most recently edited first
+pub async fn get_user_drafts(author_id: String) -> Result<Vec<pending_post::Data>, QueryError> {
+    pending_posts()
+        .await
+        .find_many(vec![
+            pending_post::author_id::equals(author_id),
+            pending_post::status::equals(SubmissionStatus::Draft),
+        ])
+        .with(pending_post::images::fetch(vec![]))
+        .order_by(pending_post::updated_at::order(Direction::Desc))
+        .exec()
+        .await
+}
+
+pub async fn get_draft(author_id: String, id: String) -> Result<Option<pending_post::Data>, QueryError> {
+    pending_posts()
+        .await
+        .find_first(vec![
+            pending_post::id::equals(id),
+            pending_post::author_id::equals(author_id),
+            pending_post::status::equals(SubmissionStatus::Draft),
+        ])
+        .with(pending_post::images::fetch(vec![]))
+        .exec()
+        .await
+}
+
+/// Also bumps `updated_at`, which keeps the draft from expiring
+pub async fn update_draft(
+    id: String,
+    mut params: Vec<pending_post::SetParam>,
+) -> Result<pending_post::Data, QueryError> {
+    let reindex = params
+        .iter()
+        .any(|p| matches!(p, pending_post::SetParam::SetExcerpt(_)));
+
+    params.push(pending_post::updated_at::set(Utc::now().into()));
+
+    PRISMA_CLIENT
+        .get()
+        .await
+        ._transaction()
+        .run(|client| async move {
+            let draft = client
+                .pending_post()
+                .update(pending_post::UniqueWhereParam::IdEquals(id), params)
+                .exec()
+                .await?;
+
+            if reindex {
+                index_excerpt(&client, ExcerptOwner::Submission(draft.id.clone()), &draft.excerpt).await?;
+            }
+
+            Ok(draft)
+        })
+        .await
+}
+
+/// Queues the draft for review as if it was just submitted
+pub async fn submit_draft(id: String) -> Result<pending_post::Data, QueryError> {
+    update_draft(
+        id,
+        vec![
+            pending_post::status::set(SubmissionStatus::Queued),
+            pending_post::submitted_at::set(Utc::now().into()),
+        ],
+    )
+    .await
+}
+
+/// Removes the drafts along with their images, returning the paths of the
+/// image files so they can be deleted too
+async fn remove_drafts(ids: Vec<String>) -> Result<Vec<String>, QueryError> {
+    let drafts = || {
+        vec![
+            pending_post::id::in_vec(ids.clone()),
+            pending_post::status::equals(SubmissionStatus::Draft),
+        ]
+    };
+
+    let paths = pending_images()
+        .await
+        .find_many(vec![pending_image::post::is(drafts())])
+        .exec()
+        .await?
+        .into_iter()
+        .map(|i| i.path)
+        .collect();
+
+    PRISMA_CLIENT
+        .get()
+        .await
+        ._transaction()
+        .run(|client| async move {
+            // Images don't cascade, since their files have to be removed too
+            client
+                .pending_image()
+                .delete_many(vec![pending_image::post::is(drafts())])
+                .exec()
+                .await?;
+
+            client.pending_post().delete_many(drafts()).exec().await
+        })
+        .await?;
+
+    Ok(paths)
+}
+
+pub async fn remove_draft(id: String) -> Result<Vec<String>, QueryError> {
+    remove_drafts(vec![id]).await
+}
+
+/// Removes drafts that haven't been edited since `before`. Returns how many
+/// there were and the paths of their image files.
+pub async fn expire_drafts(before: DateTime<FixedOffset>) -> Result<(usize, Vec<String>), QueryError> {
+    let ids = pending_posts()
+        .await
+        .find_many(vec![
+            pending_post::status::equals(SubmissionStatus::Draft),
+            pending_post::updated_at::lt(before),
+        ])
+        .select(pending_post::select!({ id }))
+        .exec()
+        .await?
+        .into_iter()
+        .map(|d| d.id)
+        .collect::<Vec<_>>();
+
+    if ids.is_empty() {
+        return Ok((0, vec![]));
+    }
+
+    let count = ids.len();
+
+    Ok((count, remove_drafts(ids).await?))
+}
+
+/// What [`rerender_posts`] did
+#[derive(Default, Serialize)]
+pub struct RenderCount {
+    /// Rendered differently than before, so updated
+    pub changed: usize,
+    pub unchanged: usize,
+    /// Submitted before the markdown was kept, so left as they are
+    pub missing_source: usize,
+}
+
+impl RenderCount {
+    /// For the job run's message
+    pub fn summary(&self) -> String {
+        format!(
+            "{} changed, {} unchanged, {} without markdown",
+            self.changed, self.unchanged, self.missing_source
+        )
+    }
+
+    /// The excerpt and citation rendered again from their sources, if they
+    /// came out any different
+    fn render(
+        &mut self,
+        excerpt: &str,
+        citation: &str,
+        excerpt_source: Option<String>,
+        citation_source: Option<String>,
+    ) -> Option<(String, String)> {
+        let (Some(excerpt_source), Some(citation_source)) = (excerpt_source, citation_source) else {
+            self.missing_source += 1;
+            return None;
+        };
+
+        let rendered = (
+            convert_and_sanitize(&excerpt_source),
+            convert_and_sanitize(&citation_source),
+        );
+
+        if rendered.0 == excerpt && rendered.1 == citation {
+            self.unchanged += 1;
+            return None;
         }
+
+        self.changed += 1;
+
+        Some(rendered)
+    }
+}
+
+/// How many rows [`rerender_posts`] renders per transaction
+const RENDER_BATCH: i64 = 100;
+
+/// Renders every post, submission and draft again from its markdown, for when
+/// the sanitizer policy changes. Goes through them in batches ordered by ID,
+/// each saved in one transaction, and reports the progress in the job run
+/// `run_id` after every batch. Rendering is idempotent, so a run that failed
+/// part way can simply be started again.
+pub async fn rerender_posts(run_id: String) -> Result<RenderCount, QueryError> {
+    let mut count = RenderCount::default();
+    let mut after = None;
+
+    loop {
+        let batch = posts()
+            .await
+            .find_many(after.take().map(post::id::gt).into_iter().collect())
+            .order_by(post::id::order(Direction::Asc))
+            .take(RENDER_BATCH)
+            .select(post::select!({ id excerpt citation excerpt_source citation_source }))
+            .exec()
+            .await?;
+
+        let Some(last) = batch.last() else {
+            break;
+        };
+
+        after = Some(last.id.clone());
+
+        let rendered = batch
+            .into_iter()
+            .filter_map(|p| {
+                count
+                    .render(&p.excerpt, &p.citation, p.excerpt_source, p.citation_source)
+                    .map(|r| (p.id, r))
+            })
+            .collect::<Vec<_>>();
+
+        PRISMA_CLIENT
+            .get()
+            .await
+            ._transaction()
+            .run(|client| async move {
+                for (id, (excerpt, citation)) in rendered {
+                    let post = client
+                        .post()
+                        .update(
+                            post::UniqueWhereParam::IdEquals(id),
+                            vec![
+                                post::excerpt::set(excerpt),
+                                post::citation::set(citation),
+                                post::updated_at::set(Utc::now().into()),
+                            ],
+                        )
+                        .exec()
+                        .await?;
+
+                    index_excerpt(&client, ExcerptOwner::Post(post.id), &post.excerpt).await?;
+                }
+
+                Ok::<_, QueryError>(())
+            })
+            .await?;
+
+        update_job_run(run_id.clone(), None, false, count.summary()).await?;
+    }
+
+    loop {
+        let batch = pending_posts()
+            .await
+            .find_many(after.take().map(pending_post::id::gt).into_iter().collect())
+            .order_by(pending_post::id::order(Direction::Asc))
+            .take(RENDER_BATCH)
+            .select(pending_post::select!({ id updated_at excerpt citation excerpt_source citation_source }))
+            .exec()
+            .await?;
+
+        let Some(last) = batch.last() else {
+            break;
+        };
+
+        after = Some(last.id.clone());
+
+        let rendered = batch
+            .into_iter()
+            .filter_map(|p| {
+                count
+                    .render(&p.excerpt, &p.citation, p.excerpt_source, p.citation_source)
+                    .map(|r| (p.id, p.updated_at, r))
+            })
+            .collect::<Vec<_>>();
+
+        PRISMA_CLIENT
+            .get()
+            .await
+            ._transaction()
+            .run(|client| async move {
+                for (id, updated_at, (excerpt, citation)) in rendered {
+                    let submission = client
+                        .pending_post()
+                        .update(
+                            pending_post::UniqueWhereParam::IdEquals(id),
+                            vec![
+                                pending_post::excerpt::set(excerpt),
+                                pending_post::citation::set(citation),
+                                // Not an edit, so drafts shouldn't last any longer for it
+                                pending_post::updated_at::set(updated_at),
+                            ],
+                        )
+                        .exec()
+                        .await?;
+
+                    index_excerpt(&client, ExcerptOwner::Submission(submission.id), &submission.excerpt).await?;
+                }
+
+                Ok::<_, QueryError>(())
+            })
+            .await?;
+
+        update_job_run(run_id.clone(), None, false, count.summary()).await?;
     }
+
+    Ok(count)
 }
@@ -0,0 +1,471 @@
+//! Renders posts as Atom, RSS 2.0 and JSON Feed 1.1 documents. Links are made
+//! absolute with the `PUBLIC_URL` environment variable.
+
+use crate::{
+    db::prisma::{image, post, Category},
+    routes::utils::misc::html_to_text,
+};
+use chrono::{DateTime, FixedOffset, Utc};
+use lazy_static::lazy_static;
+use rocket::{http::ContentType, tokio::fs};
+use serde_json::json;
+use std::{collections::HashMap, env, fmt::Write};
+
+/// How many posts are included in a feed
+pub const FEED_SIZE: u32 = 20;
+/// Longest an entry's title can be before it's cut off
+const TITLE_LENGTH: usize = 80;
+const SITE_NAME: &str = "Traveler's Abode";
+
+lazy_static! {
+    static ref PUBLIC_URL: String = env::var("PUBLIC_URL")
+        .map(|u| u.trim_end_matches('/').to_owned())
+        .unwrap_or_default();
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum FeedFormat {
+    Atom,
+    Rss,
+    Json,
+}
+
+impl FeedFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            FeedFormat::Atom => "atom",
+            FeedFormat::Rss => "rss",
+            FeedFormat::Json => "json",
+        }
+    }
+
+    pub fn content_type(self) -> ContentType {
+        let sub = match self {
+            FeedFormat::Atom => "atom+xml",
+            FeedFormat::Rss => "rss+xml",
+            FeedFormat::Json => "feed+json",
+        };
+
+        ContentType::new("application", sub).with_params(("charset", "utf-8"))
+    }
+}
+
+/// The posts of one section, or of all of them when `section` is [`None`],
+/// newest first
+pub struct Feed<'a> {
+    pub section: Option<Category>,
+    pub posts: &'a [post::Data],
+    /// Sizes of the posts' image files by path, for enclosures
+    pub file_sizes: HashMap<String, u64>,
+}
+
+impl<'a> Feed<'a> {
+    /// Looks up the sizes of the posts' image files, which are left out of the
+    /// feed if they can't be read
+    pub async fn new(section: Option<Category>, posts: &'a [post::Data]) -> Feed<'a> {
+        let mut file_sizes = HashMap::new();
+
+        for img in posts.iter().flat_map(images) {
+            if let Ok(metadata) = fs::metadata(img.path.trim_start_matches('/')).await {
+                file_sizes.insert(img.path.clone(), metadata.len());
+            }
+        }
+
+        Feed {
+            section,
+            posts,
+            file_sizes,
+        }
+    }
+
+    pub fn render(&self, format: FeedFormat) -> String {
+        match format {
+            FeedFormat::Atom => self.atom(),
+            FeedFormat::Rss => self.rss(),
+            FeedFormat::Json => self.json(),
+        }
+    }
+
+    fn title(&self) -> String {
+        match self.section {
+            Some(section) => format!("{SITE_NAME} - {}", capitalize(&section.to_string())),
+            None => SITE_NAME.to_owned(),
+        }
+    }
+
+    fn self_url(&self, format: FeedFormat) -> String {
+        let name = match self.section {
+            Some(section) => section.to_string().to_ascii_lowercase(),
+            None => "all".to_owned(),
+        };
+
+        format!("{}/feeds/{name}.{}", *PUBLIC_URL, format.extension())
+    }
+
+    /// When the newest post was confirmed, or now if there are none
+    fn updated(&self) -> DateTime<FixedOffset> {
+        self.posts
+            .first()
+            .map(|p| p.confirmed_at)
+            .unwrap_or_else(|| Utc::now().into())
+    }
+
+    fn atom(&self) -> String {
+        let mut out = String::new();
+        let self_url = self.self_url(FeedFormat::Atom);
+
+        out.push_str(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+        out.push_str(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#);
+        let _ = write!(
+            out,
+            r#"<id>{id}</id><title>{title}</title><updated>{updated}</updated><link rel="self" href="{id}"/><link rel="alternate" href="{home}/"/><author><name>{author}</name></author>"#,
+            id = escape(&self_url),
+            title = escape(&self.title()),
+            updated = self.updated().to_rfc3339(),
+            home = escape(&PUBLIC_URL),
+            author = escape(SITE_NAME),
+        );
+
+        for post in self.posts {
+            let _ = write!(
+                out,
+                r#"<entry><id>urn:uuid:{id}</id><title>{title}</title><link rel="alternate" href="{link}"/><published>{date}</published><updated>{date}</updated><content type="html">{content}</content>"#,
+                id = post.id,
+                title = escape(&entry_title(post)),
+                link = escape(&post_url(post)),
+                date = post.confirmed_at.to_rfc3339(),
+                content = escape(&content_html(post)),
+            );
+
+            for img in images(post) {
+                let _ = write!(
+                    out,
+                    r#"<link rel="enclosure" href="{}" type="{}""#,
+                    escape(&image_url(img)),
+                    mime_type(&img.path)
+                );
+
+                if let Some(size) = self.file_size(img) {
+                    let _ = write!(out, r#" length="{size}""#);
+                }
+
+                out.push_str("/>");
+            }
+
+            out.push_str("</entry>");
+        }
+
+        out.push_str("</feed>");
+
+        out
+    }
+
+    fn rss(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+        out.push_str(r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom"><channel>"#);
+        let _ = write!(
+            out,
+            r#"<title>{title}</title><link>{home}/</link><description>{title}</description><atom:link href="{self_url}" rel="self" type="application/rss+xml"/><lastBuildDate>{updated}</lastBuildDate>"#,
+            title = escape(&self.title()),
+            home = escape(&PUBLIC_URL),
+            self_url = escape(&self.self_url(FeedFormat::Rss)),
+            updated = self.updated().to_rfc2822(),
+        );
+
+        for post in self.posts {
+            let _ = write!(
+                out,
+                r#"<item><title>{title}</title><link>{link}</link><guid isPermaLink="false">{id}</guid><pubDate>{date}</pubDate><description>{content}</description>"#,
+                title = escape(&entry_title(post)),
+                link = escape(&post_url(post)),
+                id = post.id,
+                date = post.confirmed_at.to_rfc2822(),
+                content = escape(&content_html(post)),
+            );
+
+            // RSS only allows a single enclosure per item
+            if let Some(img) = images(post).first() {
+                let _ = write!(
+                    out,
+                    r#"<enclosure url="{}" length="{}" type="{}"/>"#,
+                    escape(&image_url(img)),
+                    self.file_size(img).unwrap_or(0),
+                    mime_type(&img.path)
+                );
+            }
+
+            out.push_str("</item>");
+        }
+
+        out.push_str("</channel></rss>");
+
+        out
+    }
+
+    fn json(&self) -> String {
+        let items = self
+            .posts
+            .iter()
+            .map(|post| {
+                let attachments = images(post)
+                    .iter()
+                    .map(|img| {
+                        json!({
+                            "url": image_url(img),
+                            "mime_type": mime_type(&img.path),
+                            "title": html_to_text(&img.alt_text),
+                            "size_in_bytes": self.file_size(img),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                json!({
+                    "id": post.id,
+                    "url": post_url(post),
+                    "title": entry_title(post),
+                    "content_html": content_html(post),
+                    "summary": html_to_text(&post.citation),
+                    "date_published": post.confirmed_at.to_rfc3339(),
+                    "attachments": attachments,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({
+            "version": "https://jsonfeed.org/version/1.1",
+            "title": self.title(),
+            "home_page_url": format!("{}/", *PUBLIC_URL),
+            "feed_url": self.self_url(FeedFormat::Json),
+            "authors": [{ "name": SITE_NAME }],
+            "items": items,
+        })
+        .to_string()
+    }
+
+    fn file_size(&self, img: &image::Data) -> Option<u64> {
+        self.file_sizes.get(&img.path).copied()
+    }
+}
+
+fn images(post: &post::Data) -> &[image::Data] {
+    post.images.as_deref().unwrap_or_default()
+}
+
+fn post_url(post: &post::Data) -> String {
+    format!(
+        "{}/posts/{}?id={}",
+        *PUBLIC_URL,
+        post.category.to_string().to_ascii_lowercase(),
+        post.id
+    )
+}
+
+fn image_url(img: &image::Data) -> String {
+    format!("{}{}", *PUBLIC_URL, img.path)
+}
+
+/// The start of the excerpt as plain text, since posts don't have titles
+fn entry_title(post: &post::Data) -> String {
+    let text = html_to_text(&post.excerpt);
+
+    if text.chars().count() <= TITLE_LENGTH {
+        return text;
+    }
+
+    let mut title = text.chars().take(TITLE_LENGTH - 1).collect::<String>();
+    title.truncate(title.trim_end().len());
+    title.push('…');
+
+    title
+}
+
+/// The excerpt and citation, both of which are already sanitized, followed by
+/// the post's images
+fn content_html(post: &post::Data) -> String {
+    let mut html = format!("{}<footer>{}</footer>", post.excerpt, post.citation);
+
+    for img in images(post) {
+        let _ = write!(
+            html,
+            r#"<img src="{}" alt="{}" width="{}" height="{}">"#,
+            escape(&image_url(img)),
+            escape(&html_to_text(&img.alt_text)),
+            img.width,
+            img.height
+        );
+    }
+
+    html
+}
+
+fn mime_type(path: &str) -> &'static str {
+    match path.rsplit('.').next() {
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+fn capitalize(s: &str) -> String {
+    let lower = s.to_ascii_lowercase();
+    let mut chars = lower.chars();
+
+    match chars.next() {
+        Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+        None => lower,
+    }
+}
+
+/// Escapes text for use in XML content and attribute values
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A post with one image, as if confirmed at noon on the 1st of May 2023
+    fn post() -> post::Data {
+        serde_json::from_value(json!({
+            "id": "6f1f5a8e-1c51-4a5e-9a52-2b1f0c7d2e11",
+            "submittedAt": "2023-05-01T10:00:00+00:00",
+            "confirmedAt": "2023-05-01T12:00:00+00:00",
+            "updatedAt": "2023-05-01T12:00:00+00:00",
+            "authorId": "author",
+            "category": "ISLAMISM",
+            "excerpt": "<p>Knowledge &amp; action</p>",
+            "citation": "<p>Someone, <em>Some Book</em></p>",
+            "excerptSource": null,
+            "citationSource": null,
+            "viewCount": 0,
+            "reactionCount": 0,
+            "popularity": 0.0,
+            "images": [{
+                "postId": "6f1f5a8e-1c51-4a5e-9a52-2b1f0c7d2e11",
+                "path": "/assets/images/page.png",
+                "width": 400,
+                "height": 300,
+                "order": 0,
+                "caption": null,
+                "altText": "A \"scanned\" page",
+                "transcription": null,
+            }],
+        }))
+        .unwrap()
+    }
+
+    fn render(format: FeedFormat) -> String {
+        let posts = [post()];
+        let feed = Feed {
+            section: Some(Category::Islamism),
+            posts: &posts,
+            file_sizes: HashMap::from([("/assets/images/page.png".to_owned(), 1234)]),
+        };
+
+        feed.render(format)
+    }
+
+    #[test]
+    fn escape_replaces_markup_characters() {
+        assert_eq!(
+            escape(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn escape_leaves_other_text_alone() {
+        assert_eq!(escape(""), "");
+        assert_eq!(escape("plain text, ünïcode…"), "plain text, ünïcode…");
+        // Already escaped text gets escaped again rather than passed through
+        assert_eq!(escape("&amp;"), "&amp;amp;");
+    }
+
+    #[test]
+    fn atom_feed() {
+        assert_eq!(render(FeedFormat::Atom), GOLDEN_ATOM);
+    }
+
+    #[test]
+    fn rss_feed() {
+        assert_eq!(render(FeedFormat::Rss), GOLDEN_RSS);
+    }
+
+    #[test]
+    fn json_feed() {
+        let rendered: serde_json::Value = serde_json::from_str(&render(FeedFormat::Json)).unwrap();
+        let golden: serde_json::Value = serde_json::from_str(GOLDEN_JSON).unwrap();
+
+        assert_eq!(rendered, golden);
+    }
+
+    /// Links are relative, as `PUBLIC_URL` isn't set when testing
+    const GOLDEN_ATOM: &str = concat!(
+        r#"<?xml version="1.0" encoding="utf-8"?><feed xmlns="http://www.w3.org/2005/Atom">"#,
+        r#"<id>/feeds/islamism.atom</id><title>Traveler&apos;s Abode - Islamism</title>"#,
+        r#"<updated>2023-05-01T12:00:00+00:00</updated><link rel="self" href="/feeds/islamism.atom"/>"#,
+        r#"<link rel="alternate" href="/"/><author><name>Traveler&apos;s Abode</name></author>"#,
+        r#"<entry><id>urn:uuid:6f1f5a8e-1c51-4a5e-9a52-2b1f0c7d2e11</id><title>Knowledge &amp; action</title>"#,
+        r#"<link rel="alternate" href="/posts/islamism?id=6f1f5a8e-1c51-4a5e-9a52-2b1f0c7d2e11"/>"#,
+        r#"<published>2023-05-01T12:00:00+00:00</published><updated>2023-05-01T12:00:00+00:00</updated>"#,
+        r#"<content type="html">&lt;p&gt;Knowledge &amp;amp; action&lt;/p&gt;&lt;footer&gt;&lt;p&gt;Someone, "#,
+        r#"&lt;em&gt;Some Book&lt;/em&gt;&lt;/p&gt;&lt;/footer&gt;&lt;img src=&quot;/assets/images/page.png&quot; "#,
+        r#"alt=&quot;A &amp;quot;scanned&amp;quot; page&quot; width=&quot;400&quot; height=&quot;300&quot;&gt;</content>"#,
+        r#"<link rel="enclosure" href="/assets/images/page.png" type="image/png" length="1234"/></entry></feed>"#,
+    );
+
+    const GOLDEN_RSS: &str = concat!(
+        r#"<?xml version="1.0" encoding="utf-8"?><rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom"><channel>"#,
+        r#"<title>Traveler&apos;s Abode - Islamism</title><link>/</link>"#,
+        r#"<description>Traveler&apos;s Abode - Islamism</description>"#,
+        r#"<atom:link href="/feeds/islamism.rss" rel="self" type="application/rss+xml"/>"#,
+        r#"<lastBuildDate>Mon, 01 May 2023 12:00:00 +0000</lastBuildDate>"#,
+        r#"<item><title>Knowledge &amp; action</title><link>/posts/islamism?id=6f1f5a8e-1c51-4a5e-9a52-2b1f0c7d2e11</link>"#,
+        r#"<guid isPermaLink="false">6f1f5a8e-1c51-4a5e-9a52-2b1f0c7d2e11</guid>"#,
+        r#"<pubDate>Mon, 01 May 2023 12:00:00 +0000</pubDate>"#,
+        r#"<description>&lt;p&gt;Knowledge &amp;amp; action&lt;/p&gt;&lt;footer&gt;&lt;p&gt;Someone, "#,
+        r#"&lt;em&gt;Some Book&lt;/em&gt;&lt;/p&gt;&lt;/footer&gt;&lt;img src=&quot;/assets/images/page.png&quot; "#,
+        r#"alt=&quot;A &amp;quot;scanned&amp;quot; page&quot; width=&quot;400&quot; height=&quot;300&quot;&gt;</description>"#,
+        r#"<enclosure url="/assets/images/page.png" length="1234" type="image/png"/></item></channel></rss>"#,
+    );
+
+    const GOLDEN_JSON: &str = r#"{
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": "Traveler's Abode - Islamism",
+        "home_page_url": "/",
+        "feed_url": "/feeds/islamism.json",
+        "authors": [{ "name": "Traveler's Abode" }],
+        "items": [{
+            "id": "6f1f5a8e-1c51-4a5e-9a52-2b1f0c7d2e11",
+            "url": "/posts/islamism?id=6f1f5a8e-1c51-4a5e-9a52-2b1f0c7d2e11",
+            "title": "Knowledge & action",
+            "content_html": "<p>Knowledge &amp; action</p><footer><p>Someone, <em>Some Book</em></p></footer><img src=\"/assets/images/page.png\" alt=\"A &quot;scanned&quot; page\" width=\"400\" height=\"300\">",
+            "summary": "Someone, Some Book",
+            "date_published": "2023-05-01T12:00:00+00:00",
+            "attachments": [{
+                "url": "/assets/images/page.png",
+                "mime_type": "image/png",
+                "title": "A \"scanned\" page",
+                "size_in_bytes": 1234
+            }]
+        }]
+    }"#;
+}
@@ -0,0 +1,230 @@
+//! Periodic tasks run in the background on the server's own runtime. Runs that
+//! did something or failed get recorded, see [`create_job_run`].
+
+use crate::{
+    db::util::{
+        create_job_run, delete_expired_notifications, delete_job_runs_before, delete_uploads_before, expire_drafts,
+        get_last_job_run, index_unbanded_excerpts, purge_notifications, send_digests, update_post_popularity,
+    },
+    routes::utils::misc::ImageField,
+    webhooks::deliver_due,
+};
+use chrono::Utc;
+use lazy_static::lazy_static;
+use rocket::{
+    fairing::AdHoc,
+    tokio::time::{interval_at, Duration, Instant},
+};
+use std::{env, future::Future, pin::Pin};
+
+lazy_static! {
+    static ref READ_NOTIFICATION_RETENTION: chrono::Duration = days_from_env("READ_NOTIFICATION_RETENTION_DAYS", 30);
+    static ref UNREAD_NOTIFICATION_RETENTION: chrono::Duration =
+        days_from_env("UNREAD_NOTIFICATION_RETENTION_DAYS", 90);
+    static ref JOB_RUN_RETENTION: chrono::Duration = days_from_env("JOB_RUN_RETENTION_DAYS", 30);
+    static ref DRAFT_EXPIRY: chrono::Duration = days_from_env("DRAFT_EXPIRY_DAYS", 30);
+}
+
+const MINUTE: Duration = Duration::from_secs(60);
+const HOUR: Duration = Duration::from_secs(60 * 60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A successful run's message is a short summary of what it did, or [`None`]
+/// if there was nothing to do. A failed run's is the error.
+pub type JobResult = Result<Option<String>, String>;
+
+pub struct Job {
+    pub name: &'static str,
+    /// The first run happens right after launch, unless the last recorded one
+    /// was less than this long ago
+    pub interval: Duration,
+    pub task: fn() -> Pin<Box<dyn Future<Output = JobResult> + Send>>,
+}
+
+impl Job {
+    async fn run(&self) {
+        let started_at = Utc::now();
+        let result = (self.task)().await;
+        let finished_at = Utc::now();
+
+        let (success, message) = match result {
+            Ok(Some(m)) => (true, m),
+            // Not worth a row, the frequent jobs often have nothing to do
+            Ok(None) => return,
+            Err(m) => (false, m),
+        };
+
+        if let Err(e) = create_job_run(self.name, started_at.into(), finished_at.into(), success, message).await {
+            error!("Couldn't record a run of {}: {e}", self.name);
+        }
+    }
+
+    /// When the first run after launch is due, so restarts don't rerun jobs
+    /// that ran recently
+    async fn first_run(&self) -> Instant {
+        let last = match get_last_job_run(self.name.to_owned()).await {
+            Ok(last) => last.map(|r| r.started_at),
+            Err(e) => {
+                error!("Couldn't look up the last run of {}: {e}", self.name);
+                None
+            }
+        };
+
+        let since = last
+            .and_then(|at| (Utc::now() - at.with_timezone(&Utc)).to_std().ok())
+            .unwrap_or(self.interval);
+
+        Instant::now() + self.interval.saturating_sub(since)
+    }
+}
+
+/// Every job the server runs
+pub fn all() -> Vec<Job> {
+    vec![
+        Job {
+            name: "notification_digests",
+            interval: DAY,
+            task: || {
+                Box::pin(async {
+                    send_digests()
+                        .await
+                        .map(|_| Some("Sent digests".to_owned()))
+                        .map_err(|e| e.to_string())
+                })
+            },
+        },
+        Job {
+            name: "expired_notifications",
+            interval: HOUR,
+            task: || {
+                Box::pin(async {
+                    delete_expired_notifications()
+                        .await
+                        .map(|n| (n > 0).then(|| format!("Deleted {n} expired notifications")))
+                        .map_err(|e| e.to_string())
+                })
+            },
+        },
+        Job {
+            name: "notification_retention",
+            interval: DAY,
+            task: || {
+                Box::pin(async {
+                    let now = Utc::now();
+
+                    purge_notifications(
+                        (now - *READ_NOTIFICATION_RETENTION).into(),
+                        (now - *UNREAD_NOTIFICATION_RETENTION).into(),
+                    )
+                    .await
+                    .map(|n| (n > 0).then(|| format!("Deleted {n} old notifications")))
+                    .map_err(|e| e.to_string())
+                })
+            },
+        },
+        Job {
+            name: "webhook_deliveries",
+            interval: MINUTE,
+            task: || {
+                Box::pin(async {
+                    deliver_due(None)
+                        .await
+                        .map(|n| (n > 0).then(|| format!("Delivered {n} webhooks")))
+                        .map_err(|e| e.to_string())
+                })
+            },
+        },
+        Job {
+            name: "post_popularity",
+            interval: HOUR,
+            task: || {
+                Box::pin(async {
+                    update_post_popularity()
+                        .await
+                        .map(|n| (n > 0).then(|| format!("Updated the popularity of {n} posts")))
+                        .map_err(|e| e.to_string())
+                })
+            },
+        },
+        Job {
+            name: "draft_expiry",
+            interval: DAY,
+            task: || {
+                Box::pin(async {
+                    let (count, paths) = expire_drafts((Utc::now() - *DRAFT_EXPIRY).into())
+                        .await
+                        .map_err(|e| e.to_string())?;
+
+                    // The drafts are already gone, so leftover files aren't worth failing over
+                    for path in paths {
+                        if let Err(e) = ImageField::remove_persisted(&path).await {
+                            error!("Couldn't remove image {path} of expired draft: {e}");
+                        }
+                    }
+
+                    Ok((count > 0).then(|| format!("Deleted {count} stale drafts")))
+                })
+            },
+        },
+        Job {
+            name: "job_run_retention",
+            interval: DAY,
+            task: || {
+                Box::pin(async {
+                    delete_job_runs_before((Utc::now() - *JOB_RUN_RETENTION).into())
+                        .await
+                        .map(|n| (n > 0).then(|| format!("Deleted {n} old job runs")))
+                        .map_err(|e| e.to_string())
+                })
+            },
+        },
+        Job {
+            name: "excerpt_bands",
+            interval: HOUR,
+            task: || {
+                Box::pin(async {
+                    index_unbanded_excerpts()
+                        .await
+                        .map(|n| (n > 0).then(|| format!("Indexed {n} excerpts for similarity checks")))
+                        .map_err(|e| e.to_string())
+                })
+            },
+        },
+        Job {
+            name: "upload_log",
+            interval: HOUR,
+            task: || {
+                Box::pin(async {
+                    delete_uploads_before((Utc::now() - chrono::Duration::days(1)).into())
+                        .await
+                        .map(|n| (n > 0).then(|| format!("Deleted {n} expired upload records")))
+                        .map_err(|e| e.to_string())
+                })
+            },
+        },
+    ]
+}
+
+/// Spawns a task per job once the server has launched
+pub fn fairing(jobs: Vec<Job>) -> AdHoc {
+    AdHoc::on_liftoff("Scheduled jobs", |_| {
+        Box::pin(async move {
+            for job in jobs {
+                rocket::tokio::spawn(async move {
+                    let mut interval = interval_at(job.first_run().await, job.interval);
+
+                    loop {
+                        interval.tick().await;
+                        job.run().await;
+                    }
+                });
+            }
+        })
+    })
+}
+
+fn days_from_env(var: &str, default: i64) -> chrono::Duration {
+    let days = env::var(var).ok().and_then(|d| d.parse().ok()).unwrap_or(default);
+
+    chrono::Duration::days(days)
+}
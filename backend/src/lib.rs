@@ -17,4 +17,8 @@ extern crate rocket;
 extern crate serde;
 
 pub mod db;
+pub mod feeds;
+pub mod jobs;
 pub mod routes;
+pub mod similarity;
+pub mod webhooks;
@@ -1,15 +1,42 @@
 #[macro_use]
 extern crate rocket;
 
-use backend::routes::{
-    notifications::{delete_notification, get_notifications, patch_notifications},
-    posts::{delete_post, get_author_posts, get_author_section_posts, get_post, get_section_posts},
-    sections::sections,
-    sign_in::sign_in,
-    sign_up::sign_up,
-    submissions::{
-        confirm_submission, get_author_section_submissions, get_author_submissions, get_section_submissions,
-        get_submission, new_submission, new_submission_image, reject_submission,
+use backend::{
+    jobs,
+    routes::{
+        admin::{
+            delete_webhook, export_audit_events, get_audit_events, get_job_runs, get_section_policies,
+            get_webhook_deliveries, get_webhooks, new_announcement, new_webhook, put_section_policy, rerender_posts,
+        },
+        collections::{
+            delete_bookmark, delete_collection, get_my_collection, get_my_collections, get_public_collection,
+            new_bookmark, new_collection, patch_collection, reorder_collection,
+        },
+        comments::{delete_comment, get_comment_queue, get_comments, new_comment, patch_comment, review_comment},
+        drafts::{delete_draft, get_my_draft, get_my_drafts, new_draft, patch_draft, submit_my_draft},
+        feeds::get_feed,
+        notifications::{
+            delete_notification, get_notification_count, get_notification_preferences, get_notifications,
+            notification_stream, patch_notifications, put_notification_preferences, read_all_notifications,
+        },
+        posts::{
+            delete_post, delete_post_image, delete_reaction, get_author_posts, get_author_section_posts,
+            get_daily_post, get_post, get_random_post, get_scheduled_posts, get_section_posts, new_post_image,
+            new_post_view, patch_post_images, put_reaction,
+        },
+        rejections::{
+            delete_canned_response, get_canned_responses, get_rejection_reasons, get_rejection_stats,
+            new_canned_response, new_rejection_reason, patch_canned_response, patch_rejection_reason,
+        },
+        reports::{get_report_queue, new_report, resolve_report_action},
+        sections::sections,
+        sign_in::sign_in,
+        sign_up::sign_up,
+        submissions::{
+            assign_submission, claim_submission, confirm_submission, get_author_section_submissions,
+            get_author_submissions, get_my_reviews, get_section_submissions, get_submission, new_submission,
+            new_submission_image, reject_submission, release_submission,
+        },
     },
 };
 use rocket::fs::{relative, FileServer};
@@ -30,6 +57,11 @@ async fn rocket() -> _ {
                 sign_in,
                 sign_up,
                 get_notifications,
+                notification_stream,
+                get_notification_count,
+                read_all_notifications,
+                get_notification_preferences,
+                put_notification_preferences,
                 patch_notifications,
                 delete_notification,
                 sections,
@@ -37,16 +69,74 @@ async fn rocket() -> _ {
                 get_author_posts,
                 get_author_section_posts,
                 get_post,
+                get_random_post,
+                get_daily_post,
+                get_scheduled_posts,
                 delete_post,
+                new_post_image,
+                patch_post_images,
+                delete_post_image,
+                put_reaction,
+                delete_reaction,
+                new_post_view,
+                get_feed,
                 get_submission,
                 get_author_submissions,
                 get_section_submissions,
                 get_author_section_submissions,
                 new_submission,
                 new_submission_image,
+                new_draft,
+                get_my_drafts,
+                get_my_draft,
+                patch_draft,
+                submit_my_draft,
+                delete_draft,
                 confirm_submission,
-                reject_submission
+                reject_submission,
+                claim_submission,
+                release_submission,
+                assign_submission,
+                get_my_reviews,
+                get_section_policies,
+                put_section_policy,
+                get_rejection_reasons,
+                new_rejection_reason,
+                patch_rejection_reason,
+                get_canned_responses,
+                new_canned_response,
+                patch_canned_response,
+                delete_canned_response,
+                get_rejection_stats,
+                new_announcement,
+                get_job_runs,
+                rerender_posts,
+                new_webhook,
+                get_webhooks,
+                delete_webhook,
+                get_webhook_deliveries,
+                get_audit_events,
+                export_audit_events,
+                get_my_collections,
+                new_collection,
+                get_my_collection,
+                patch_collection,
+                delete_collection,
+                new_bookmark,
+                delete_bookmark,
+                reorder_collection,
+                get_public_collection,
+                get_comments,
+                new_comment,
+                patch_comment,
+                delete_comment,
+                get_comment_queue,
+                review_comment,
+                new_report,
+                get_report_queue,
+                resolve_report_action
             ],
         )
         .mount("/assets", FileServer::from(relative!("assets")))
+        .attach(jobs::fairing(jobs::all()))
 }
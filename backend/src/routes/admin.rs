@@ -0,0 +1,407 @@
+use crate::{
+    db::{
+        prisma::{
+            audit_event, job_run, section_policy, webhook, webhook_delivery, AuditAction, Category, Role,
+            WebhookDeliveryStatus, WebhookEvent,
+        },
+        util,
+        util::{fan_out_notification, AuditFilter, NotificationContent},
+    },
+    routes::utils::{
+        audit::{audit, snapshot},
+        headers::{AuthHeader, AuthLevel, RequestMeta, Verifiable},
+        misc::{convert_and_sanitize, PaginationFields, TimestampField, UuidField},
+        responses::Download,
+    },
+};
+use chrono::{DateTime, FixedOffset, Utc};
+use rocket::{
+    http::{ContentType, Status},
+    serde::json::{json, Json, Value},
+};
+use sanitizer::prelude::*;
+use serde::Serialize;
+use uuid::Uuid;
+use validator::Validate;
+
+/// Fans the announcement out in the background, so this returns before every
+/// user has been notified.
+#[post("/admin/announcements", data = "<announcement>")]
+pub async fn new_announcement(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    announcement: Json<AnnouncementBody>,
+) -> Result<Status, Status> {
+    let _c = auth_header.verify()?;
+
+    let mut announcement = announcement.into_inner();
+
+    announcement.validate().map_err(|_| Status::BadRequest)?;
+    announcement.sanitize();
+
+    if announcement.expires_at.map_or(false, |e| e <= Utc::now()) {
+        return Err(Status::BadRequest);
+    }
+
+    let AnnouncementBody {
+        title,
+        body,
+        role,
+        expires_at,
+    } = announcement;
+
+    rocket::tokio::spawn(async move {
+        let notif = NotificationContent::Announcement { title, body };
+
+        if let Err(e) = fan_out_notification(&notif, role, expires_at).await {
+            error!("Couldn't send announcement: {e}");
+        }
+    });
+
+    Ok(Status::Accepted)
+}
+
+/// Most recent runs first, optionally only those of one job
+#[get("/admin/jobs?<job>&<pagination..>")]
+pub async fn get_job_runs(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    job: Option<String>,
+    pagination: PaginationFields,
+) -> Result<Json<Vec<job_run::Data>>, Status> {
+    let _c = auth_header.verify()?;
+
+    let runs = util::get_job_runs(job, pagination)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(runs))
+}
+
+/// Renders every post and submission again from its markdown. Meant to be run
+/// after the sanitizer policy changes, posts without markdown are left alone.
+/// Runs in the background, the returned job run reports its progress and can
+/// be followed at `/admin/jobs?job=rerender_posts`.
+#[post("/admin/posts/render")]
+pub async fn rerender_posts(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    meta: RequestMeta,
+) -> Result<(Status, Json<job_run::Data>), Status> {
+    let claims = auth_header.verify()?;
+
+    let run = util::start_job_run("rerender_posts", "Started".to_owned())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    audit(
+        &claims,
+        meta,
+        AuditAction::RerenderPosts,
+        "posts".to_owned(),
+        None,
+        snapshot(&run),
+    )
+    .await;
+
+    let run_id = run.id.clone();
+
+    rocket::tokio::spawn(async move {
+        let (success, message) = match util::rerender_posts(run_id.clone()).await {
+            Ok(count) => (true, count.summary()),
+            Err(e) => (false, e.to_string()),
+        };
+
+        if let Err(e) = util::update_job_run(run_id, Some(Utc::now().into()), success, message).await {
+            error!("Couldn't record the end of rerendering posts: {e}");
+        }
+    });
+
+    Ok((Status::Accepted, Json(run)))
+}
+
+/// The secret is only ever returned here, it's needed for verifying the
+/// signature on deliveries
+#[post("/admin/webhooks", data = "<webhook>")]
+pub async fn new_webhook(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    webhook: Json<WebhookCreationBody>,
+) -> Result<Value, Status> {
+    let _c = auth_header.verify()?;
+
+    webhook.validate().map_err(|_| Status::BadRequest)?;
+
+    if webhook.events.is_empty() {
+        return Err(Status::BadRequest);
+    }
+
+    let secret = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+
+    let webhook = util::create_webhook(webhook.url.clone(), secret.clone(), webhook.events.clone())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(json!({ "webhook": WebhookBody::from(webhook), "secret": secret }))
+}
+
+#[get("/admin/webhooks")]
+pub async fn get_webhooks(auth_header: AuthHeader<{ AuthLevel::Admin }>) -> Result<Json<Vec<WebhookBody>>, Status> {
+    let _c = auth_header.verify()?;
+
+    let webhooks = util::get_webhooks()
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .into_iter()
+        .map(WebhookBody::from)
+        .collect();
+
+    Ok(Json(webhooks))
+}
+
+/// Also deletes the webhook's delivery log
+#[delete("/admin/webhooks", data = "<webhook>")]
+pub async fn delete_webhook(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    webhook: Json<WebhookDeletionBody>,
+) -> Result<(), Status> {
+    let _c = auth_header.verify()?;
+
+    let deleted = util::remove_webhook(webhook.id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if deleted == 0 {
+        return Err(Status::NotFound);
+    }
+
+    Ok(())
+}
+
+#[get("/admin/webhooks/deliveries?<webhook>&<status>&<pagination..>")]
+pub async fn get_webhook_deliveries(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    webhook: Option<UuidField>,
+    status: Option<DeliveryStatusField>,
+    pagination: PaginationFields,
+) -> Result<Json<Vec<webhook_delivery::Data>>, Status> {
+    let _c = auth_header.verify()?;
+
+    let status = status.map(|s| match s {
+        DeliveryStatusField::Pending => WebhookDeliveryStatus::Pending,
+        DeliveryStatusField::Delivered => WebhookDeliveryStatus::Delivered,
+        DeliveryStatusField::Failed => WebhookDeliveryStatus::Failed,
+    });
+
+    let deliveries = util::get_webhook_deliveries(webhook.map(|w| w.to_string()), status, pagination)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(deliveries))
+}
+
+/// Sections without a policy need a single approval
+#[get("/admin/policies")]
+pub async fn get_section_policies(auth_header: AuthHeader<{ AuthLevel::Admin }>) -> Result<Json<Vec<Value>>, Status> {
+    let _c = auth_header.verify()?;
+
+    let stored = util::get_section_policies()
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let policies = Category::ALL
+        .into_iter()
+        .map(|section| {
+            let required = stored
+                .iter()
+                .find(|p| p.category == section)
+                .map_or(1, |p| p.required_approvals);
+
+            json!({ "section": section, "required_approvals": required })
+        })
+        .collect();
+
+    Ok(Json(policies))
+}
+
+#[put("/admin/policies/<section>", data = "<policy>")]
+pub async fn put_section_policy(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    section: Category,
+    policy: Json<SectionPolicyBody>,
+) -> Result<Json<section_policy::Data>, Status> {
+    let _c = auth_header.verify()?;
+
+    policy.validate().map_err(|_| Status::BadRequest)?;
+
+    let policy = util::set_required_approvals(section, policy.required_approvals)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(policy))
+}
+
+/// Newest first
+#[get("/admin/audit?<actor>&<action>&<target>&<since>&<until>&<pagination..>")]
+pub async fn get_audit_events(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    actor: Option<UuidField>,
+    action: Option<AuditActionField>,
+    target: Option<String>,
+    since: Option<TimestampField>,
+    until: Option<TimestampField>,
+    pagination: PaginationFields,
+) -> Result<Json<Vec<audit_event::Data>>, Status> {
+    let _c = auth_header.verify()?;
+
+    let filter = audit_filter(actor, action, target, since, until);
+
+    let events = util::get_audit_events(filter, pagination)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(events))
+}
+
+/// The matching events oldest first as JSON Lines, one event per line
+#[get("/admin/audit/export?<actor>&<action>&<target>&<since>&<until>")]
+pub async fn export_audit_events(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    actor: Option<UuidField>,
+    action: Option<AuditActionField>,
+    target: Option<String>,
+    since: Option<TimestampField>,
+    until: Option<TimestampField>,
+) -> Result<Download, Status> {
+    let _c = auth_header.verify()?;
+
+    let filter = audit_filter(actor, action, target, since, until);
+
+    let events = util::export_audit_events(filter)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let mut body = String::new();
+
+    for event in events {
+        body.push_str(&serde_json::to_string(&event).map_err(|_| Status::InternalServerError)?);
+        body.push('\n');
+    }
+
+    Ok(Download {
+        filename: format!("audit-{}.jsonl", Utc::now().format("%Y%m%d%H%M%S")),
+        content_type: ContentType::new("application", "jsonl"),
+        body,
+    })
+}
+
+fn audit_filter(
+    actor: Option<UuidField>,
+    action: Option<AuditActionField>,
+    target: Option<String>,
+    since: Option<TimestampField>,
+    until: Option<TimestampField>,
+) -> AuditFilter {
+    AuditFilter {
+        actor_id: actor.map(|a| a.to_string()),
+        action: action.map(AuditAction::from),
+        target_id: target.filter(|t| !t.is_empty()),
+        since: since.map(|t| *t),
+        until: until.map(|t| *t),
+    }
+}
+
+#[derive(Deserialize, Validate)]
+pub struct SectionPolicyBody {
+    #[validate(range(min = 1, max = 10))]
+    pub(crate) required_approvals: i32,
+}
+
+#[derive(FromFormField)]
+pub enum AuditActionField {
+    #[field(value = "confirm_submission")]
+    ConfirmSubmission,
+    #[field(value = "reject_submission")]
+    RejectSubmission,
+    #[field(value = "delete_post")]
+    DeletePost,
+    #[field(value = "delete_notifications")]
+    DeleteNotifications,
+    #[field(value = "moderate_comment")]
+    ModerateComment,
+    #[field(value = "resolve_report")]
+    ResolveReport,
+    #[field(value = "rerender_posts")]
+    RerenderPosts,
+}
+
+impl From<AuditActionField> for AuditAction {
+    fn from(value: AuditActionField) -> Self {
+        match value {
+            AuditActionField::ConfirmSubmission => AuditAction::ConfirmSubmission,
+            AuditActionField::RejectSubmission => AuditAction::RejectSubmission,
+            AuditActionField::DeletePost => AuditAction::DeletePost,
+            AuditActionField::DeleteNotifications => AuditAction::DeleteNotifications,
+            AuditActionField::ModerateComment => AuditAction::ModerateComment,
+            AuditActionField::ResolveReport => AuditAction::ResolveReport,
+            AuditActionField::RerenderPosts => AuditAction::RerenderPosts,
+        }
+    }
+}
+
+#[derive(FromFormField)]
+pub enum DeliveryStatusField {
+    Pending,
+    Delivered,
+    Failed,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct WebhookCreationBody {
+    #[validate(url, length(max = 2048))]
+    pub(crate) url: String,
+    pub(crate) events: Vec<WebhookEvent>,
+}
+
+#[derive(Deserialize)]
+pub struct WebhookDeletionBody {
+    pub(crate) id: Uuid,
+}
+
+/// A [`webhook::Data`] without the secret
+#[derive(Serialize)]
+pub struct WebhookBody {
+    pub id: String,
+    pub created_at: DateTime<FixedOffset>,
+    pub url: String,
+    pub active: bool,
+    pub events: Vec<WebhookEvent>,
+}
+
+impl From<webhook::Data> for WebhookBody {
+    fn from(value: webhook::Data) -> Self {
+        let events = value
+            .subscriptions
+            .unwrap_or_default()
+            .into_iter()
+            .map(|s| s.event)
+            .collect();
+
+        Self {
+            id: value.id,
+            created_at: value.created_at,
+            url: value.url,
+            active: value.active,
+            events,
+        }
+    }
+}
+
+#[derive(Deserialize, Validate, Sanitize)]
+pub struct AnnouncementBody {
+    #[sanitize(trim)]
+    #[validate(length(min = 1, max = 200))]
+    pub(crate) title: String,
+    #[sanitize(trim, custom(convert_and_sanitize))]
+    #[validate(length(min = 1, max = 5000))]
+    pub(crate) body: String,
+    /// Only users with this role get the announcement, everyone does if unset
+    pub(crate) role: Option<Role>,
+    pub(crate) expires_at: Option<DateTime<FixedOffset>>,
+}
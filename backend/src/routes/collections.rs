@@ -0,0 +1,271 @@
+use crate::{
+    db::{
+        prisma::{collection, post},
+        util::{
+            add_bookmark, create_collection, get_collection, get_published_post_by_id, get_user_collections,
+            remove_bookmark, remove_collection, reorder_bookmarks, update_collection,
+        },
+    },
+    routes::utils::{
+        headers::{AuthHeader, Verifiable},
+        jwt::Claims,
+        misc::UuidField,
+    },
+};
+use ammonia::clean;
+use chrono::{DateTime, FixedOffset};
+use rocket::{
+    http::Status,
+    serde::json::{json, Json, Value},
+};
+use sanitizer::prelude::*;
+use std::collections::HashSet;
+use uuid::Uuid;
+use validator::Validate;
+
+#[get("/me/collections")]
+pub async fn get_my_collections(auth_header: AuthHeader) -> Result<Json<Vec<CollectionBody>>, Status> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    let collections = get_user_collections(sub.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .into_iter()
+        .map(CollectionBody::from)
+        .collect();
+
+    Ok(Json(collections))
+}
+
+#[post("/me/collections", data = "<body>")]
+pub async fn new_collection(
+    auth_header: AuthHeader,
+    body: Json<CollectionCreationBody>,
+) -> Result<Json<CollectionBody>, Status> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    let mut body = body.into_inner();
+
+    body.validate().map_err(|_| Status::BadRequest)?;
+    body.sanitize();
+
+    if body.name.is_empty() {
+        return Err(Status::BadRequest);
+    }
+
+    let collection = create_collection(sub.to_string(), body.name, body.public)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(collection.into()))
+}
+
+#[get("/me/collections/<id>")]
+pub async fn get_my_collection(auth_header: AuthHeader, id: UuidField) -> Result<Json<CollectionBody>, Status> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    let collection = owned_collection(sub, *id).await?;
+
+    Ok(Json(collection.into()))
+}
+
+#[patch("/me/collections/<id>", data = "<body>")]
+pub async fn patch_collection(
+    auth_header: AuthHeader,
+    id: UuidField,
+    body: Json<CollectionPatchBody>,
+) -> Result<Value, Status> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    body.validate().map_err(|_| Status::BadRequest)?;
+
+    let mut params = vec![];
+
+    if let Some(name) = body.name.as_deref() {
+        let name = clean(name.trim());
+
+        if name.is_empty() {
+            return Err(Status::BadRequest);
+        }
+
+        params.push(collection::name::set(name));
+    }
+
+    if let Some(public) = body.public {
+        params.push(collection::public::set(public));
+    }
+
+    let updated = update_collection(sub.to_string(), id.to_string(), params)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if updated == 0 {
+        return Err(Status::NotFound);
+    }
+
+    Ok(json!({ "id": id.to_string() }))
+}
+
+#[delete("/me/collections/<id>")]
+pub async fn delete_collection(auth_header: AuthHeader, id: UuidField) -> Result<Value, Status> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    let deleted = remove_collection(sub.to_string(), id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if deleted == 0 {
+        return Err(Status::NotFound);
+    }
+
+    Ok(json!({ "id": id.to_string() }))
+}
+
+/// Adds the post to the end of the collection
+#[post("/me/collections/<id>/posts", data = "<body>")]
+pub async fn new_bookmark(auth_header: AuthHeader, id: UuidField, body: Json<BookmarkBody>) -> Result<Value, Status> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    owned_collection(sub, *id).await?;
+
+    get_published_post_by_id(body.id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    add_bookmark(id.to_string(), body.id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(json!({ "id": body.id }))
+}
+
+#[delete("/me/collections/<id>/posts", data = "<body>")]
+pub async fn delete_bookmark(
+    auth_header: AuthHeader,
+    id: UuidField,
+    body: Json<BookmarkBody>,
+) -> Result<Value, Status> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    owned_collection(sub, *id).await?;
+
+    let deleted = remove_bookmark(id.to_string(), body.id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if deleted == 0 {
+        return Err(Status::NotFound);
+    }
+
+    Ok(json!({ "id": body.id }))
+}
+
+/// Takes every post in the collection, in the new order
+#[put("/me/collections/<id>/posts", data = "<body>")]
+pub async fn reorder_collection(
+    auth_header: AuthHeader,
+    id: UuidField,
+    body: Json<BookmarkOrderBody>,
+) -> Result<Json<CollectionBody>, Status> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    let collection = owned_collection(sub, *id).await?;
+
+    let current = collection
+        .bookmarks
+        .unwrap_or_default()
+        .into_iter()
+        .map(|b| b.post_id)
+        .collect::<HashSet<_>>();
+    let ids = body.ids.iter().map(Uuid::to_string).collect::<Vec<_>>();
+
+    if ids.len() != current.len() || ids.iter().collect::<HashSet<_>>() != current.iter().collect() {
+        return Err(Status::BadRequest);
+    }
+
+    reorder_bookmarks(id.to_string(), ids)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let collection = owned_collection(sub, *id).await?;
+
+    Ok(Json(collection.into()))
+}
+
+/// Anyone can view public collections, private ones act as if they don't exist
+#[get("/collections/<id>")]
+pub async fn get_public_collection(id: UuidField) -> Result<Json<CollectionBody>, Status> {
+    let collection = get_collection(id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .filter(|c| c.public)
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(collection.into()))
+}
+
+/// Private collections are treated as not found for anyone but their owner
+async fn owned_collection(owner: Uuid, id: Uuid) -> Result<collection::Data, Status> {
+    get_collection(id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .filter(|c| c.owner_id == owner.to_string())
+        .ok_or(Status::NotFound)
+}
+
+#[derive(Deserialize, Validate, Sanitize)]
+pub struct CollectionCreationBody {
+    #[sanitize(trim, custom(clean))]
+    #[validate(length(min = 1, max = 100))]
+    pub(crate) name: String,
+    #[serde(default)]
+    pub(crate) public: bool,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct CollectionPatchBody {
+    #[validate(length(min = 1, max = 100))]
+    pub(crate) name: Option<String>,
+    pub(crate) public: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct BookmarkBody {
+    /// The post's ID
+    pub(crate) id: Uuid,
+}
+
+#[derive(Deserialize)]
+pub struct BookmarkOrderBody {
+    pub(crate) ids: Vec<Uuid>,
+}
+
+#[derive(Serialize)]
+pub struct CollectionBody {
+    pub id: String,
+    pub created_at: DateTime<FixedOffset>,
+    pub name: String,
+    pub public: bool,
+    /// Where the collection can be viewed by others, if it's public
+    pub url: Option<String>,
+    /// Only included when a single collection is fetched
+    pub posts: Option<Vec<post::Data>>,
+}
+
+impl From<collection::Data> for CollectionBody {
+    fn from(value: collection::Data) -> Self {
+        let posts = value
+            .bookmarks
+            .map(|bookmarks| bookmarks.into_iter().filter_map(|b| b.post).map(|p| *p).collect());
+
+        Self {
+            url: value.public.then(|| format!("/collections/{}", value.id)),
+            id: value.id,
+            created_at: value.created_at,
+            name: value.name,
+            public: value.public,
+            posts,
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use crate::{
+    db::{
+        prisma::{comment, AuditAction, CommentStatus},
+        util::{
+            create_comment, get_comment, get_held_comments, get_post_comments, get_published_post_by_id,
+            moderate_comment, remove_comment, update_comment_body,
+        },
+    },
+    routes::utils::{
+        audit::{audit, snapshot},
+        headers::{AuthHeader, AuthLevel, RequestMeta, Verifiable},
+        jwt::Claims,
+        misc::{convert_and_sanitize, PaginationFields, UuidField},
+    },
+};
+use chrono::{Duration, Utc};
+use rocket::{
+    http::Status,
+    serde::json::{json, Json, Value},
+};
+use uuid::Uuid;
+use validator::Validate;
+
+/// How long authors have to edit their comments after posting them
+const EDIT_WINDOW_MINUTES: i64 = 15;
+
+/// The whole thread is flat, replies point to their parent with `parent_id`
+#[get("/posts/comments?<post>&<pagination..>")]
+pub async fn get_comments(post: UuidField, pagination: PaginationFields) -> Result<Json<Vec<comment::Data>>, Status> {
+    let comments = get_post_comments(post.to_string(), pagination)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(comments))
+}
+
+/// Comments from new accounts come back with a `HELD` status, and only show up
+/// once an admin approves them
+#[post("/posts/comments", data = "<body>")]
+pub async fn new_comment(auth_header: AuthHeader, body: Json<CommentBody>) -> Result<Json<comment::Data>, Status> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    body.validate().map_err(|_| Status::BadRequest)?;
+
+    let text = sanitize_comment(&body.body)?;
+    let post_id = body.post_id.to_string();
+
+    get_published_post_by_id(post_id.clone())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    if let Some(parent_id) = body.parent_id {
+        let parent = get_comment(parent_id.to_string())
+            .await
+            .map_err(|_| Status::InternalServerError)?
+            .ok_or(Status::NotFound)?;
+
+        if parent.post_id != post_id || parent.status != CommentStatus::Visible {
+            return Err(Status::BadRequest);
+        }
+    }
+
+    let comment = create_comment(post_id, sub.to_string(), body.parent_id.map(|p| p.to_string()), text)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(comment))
+}
+
+/// Only the author can edit, and only for [`EDIT_WINDOW_MINUTES`]
+#[patch("/posts/comments", data = "<body>")]
+pub async fn patch_comment(
+    auth_header: AuthHeader,
+    body: Json<CommentEditBody>,
+) -> Result<Json<comment::Data>, Status> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    body.validate().map_err(|_| Status::BadRequest)?;
+
+    let comment = get_comment(body.id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .filter(|c| c.status != CommentStatus::Removed)
+        .ok_or(Status::NotFound)?;
+
+    if comment.author_id != sub.to_string() {
+        return Err(Status::Forbidden);
+    }
+
+    if Utc::now() - Duration::minutes(EDIT_WINDOW_MINUTES) > comment.created_at {
+        return Err(Status::Forbidden);
+    }
+
+    let text = sanitize_comment(&body.body)?;
+
+    let comment = update_comment_body(comment.id, text)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(comment))
+}
+
+/// Authors can remove their own comments, admins can remove anyone's
+#[delete("/posts/comments", data = "<body>")]
+pub async fn delete_comment(auth_header: AuthHeader, body: Json<CommentDeletionBody>) -> Result<Value, Status> {
+    let Claims { sub, admin, .. } = auth_header.verify()?;
+
+    let comment = get_comment(body.id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .filter(|c| c.status != CommentStatus::Removed)
+        .ok_or(Status::NotFound)?;
+
+    if !admin && comment.author_id != sub.to_string() {
+        return Err(Status::Forbidden);
+    }
+
+    remove_comment(comment.id)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(json!({ "id": body.id }))
+}
+
+/// Held comments, oldest first
+#[get("/admin/comments?<pagination..>")]
+pub async fn get_comment_queue(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    pagination: PaginationFields,
+) -> Result<Json<Vec<comment::Data>>, Status> {
+    let _c = auth_header.verify()?;
+
+    let comments = get_held_comments(pagination)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(comments))
+}
+
+#[patch("/admin/comments", data = "<body>")]
+pub async fn review_comment(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    meta: RequestMeta,
+    body: Json<CommentModerationBody>,
+) -> Result<Json<comment::Data>, Status> {
+    let claims = auth_header.verify()?;
+
+    let before = get_comment(body.id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let comment = moderate_comment(body.id.to_string(), body.approve)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    audit(
+        &claims,
+        meta,
+        AuditAction::ModerateComment,
+        comment.id.clone(),
+        before.as_ref().and_then(snapshot),
+        snapshot(&comment),
+    )
+    .await;
+
+    Ok(Json(comment))
+}
+
+/// Rejects comments that end up empty after sanitization
+fn sanitize_comment(text: &str) -> Result<String, Status> {
+    let text = convert_and_sanitize(text.trim());
+
+    if text.trim().is_empty() {
+        return Err(Status::BadRequest);
+    }
+
+    Ok(text)
+}
+
+#[derive(Deserialize, Validate)]
+pub struct CommentBody {
+    pub(crate) post_id: Uuid,
+    /// Set when replying to another comment on the same post
+    pub(crate) parent_id: Option<Uuid>,
+    /// Markdown
+    #[validate(length(min = 1, max = 5000))]
+    pub(crate) body: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct CommentEditBody {
+    pub(crate) id: Uuid,
+    #[validate(length(min = 1, max = 5000))]
+    pub(crate) body: String,
+}
+
+#[derive(Deserialize)]
+pub struct CommentDeletionBody {
+    pub(crate) id: Uuid,
+}
+
+#[derive(Deserialize)]
+pub struct CommentModerationBody {
+    pub(crate) id: Uuid,
+    /// Removes the comment when false
+    pub(crate) approve: bool,
+}
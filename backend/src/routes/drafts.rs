@@ -0,0 +1,209 @@
+use crate::{
+    db::{
+        prisma::{pending_post, Category, SubmissionStatus},
+        util::{
+            check_submission, create_pending_post, get_draft, get_user_drafts, remove_draft, submit_draft, update_draft,
+        },
+    },
+    routes::{
+        submissions::PostSubmissionResponse,
+        utils::{
+            headers::{AuthHeader, Verifiable},
+            jwt::Claims,
+            misc::{convert_and_sanitize, ImageField, UuidField},
+            responses::SubmissionError,
+        },
+    },
+    webhooks::{self, Event},
+};
+use rocket::{
+    form::{Form, Strict},
+    http::Status,
+    serde::json::{json, Json, Value},
+};
+use uuid::Uuid;
+
+/// Drafts keep the markdown the author wrote along with the HTML rendered from
+/// it. Images are added with `new_submission_image` like with any other
+/// submission.
+#[post("/submissions/<section>/drafts", data = "<draft>")]
+pub async fn new_draft(
+    auth_header: AuthHeader,
+    section: Category,
+    draft: Form<Strict<DraftForm>>,
+) -> Result<PostSubmissionResponse, Status> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    let id = Uuid::new_v4();
+
+    create_pending_post(
+        section,
+        id,
+        sub,
+        draft.excerpt.clone().unwrap_or_default().trim().to_owned(),
+        draft.citation.clone().unwrap_or_default().trim().to_owned(),
+        SubmissionStatus::Draft,
+    )
+    .await
+    .map_err(|_| Status::InternalServerError)?;
+
+    Ok(PostSubmissionResponse { id: id.to_string() })
+}
+
+/// Most recently edited first
+#[get("/me/drafts", rank = 2)]
+pub async fn get_my_drafts(auth_header: AuthHeader) -> Result<Json<Vec<pending_post::Data>>, Status> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    let drafts = get_user_drafts(sub.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(drafts))
+}
+
+#[get("/me/drafts?<id>", rank = 1)]
+pub async fn get_my_draft(auth_header: AuthHeader, id: UuidField) -> Result<Json<pending_post::Data>, Status> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    let draft = get_draft(sub.to_string(), id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(draft))
+}
+
+/// Fields that are left out stay as they are
+#[patch("/me/drafts?<id>", data = "<draft>")]
+pub async fn patch_draft(
+    auth_header: AuthHeader,
+    id: UuidField,
+    draft: Form<Strict<DraftForm>>,
+) -> Result<Json<pending_post::Data>, Status> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    get_draft(sub.to_string(), id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let mut params = vec![];
+
+    if let Some(section) = draft.section {
+        params.push(pending_post::category::set(section));
+    }
+
+    if let Some(excerpt) = draft.excerpt.as_deref().map(str::trim) {
+        params.push(pending_post::excerpt::set(convert_and_sanitize(excerpt)));
+        params.push(pending_post::excerpt_source::set(Some(excerpt.to_owned())));
+    }
+
+    if let Some(citation) = draft.citation.as_deref().map(str::trim) {
+        params.push(pending_post::citation::set(convert_and_sanitize(citation)));
+        params.push(pending_post::citation_source::set(Some(citation.to_owned())));
+    }
+
+    update_draft(id.to_string(), params)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // Fetched again for its images
+    let draft = get_draft(sub.to_string(), id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(draft))
+}
+
+/// Queues the draft for review. It has to meet the same requirements as a
+/// submission made with `new_submission`, quotas included.
+#[post("/me/drafts/submit?<id>")]
+pub async fn submit_my_draft(
+    auth_header: AuthHeader,
+    id: UuidField,
+) -> Result<Json<pending_post::Data>, SubmissionError> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    let draft = get_draft(sub.to_string(), id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    // Drafts from before the markdown was kept have it in place of the HTML
+    let excerpt_source = draft.excerpt_source.unwrap_or(draft.excerpt);
+    let citation_source = draft.citation_source.unwrap_or(draft.citation);
+
+    if !(10..1500).contains(&excerpt_source.len()) || !(10..200).contains(&citation_source.len()) {
+        return Err(Status::UnprocessableEntity.into());
+    }
+
+    let excerpt = convert_and_sanitize(&excerpt_source);
+
+    if let Some(violation) = check_submission(sub.to_string(), &excerpt)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+    {
+        return Err(SubmissionError::Refused(violation));
+    }
+
+    update_draft(
+        draft.id.clone(),
+        vec![
+            pending_post::excerpt::set(excerpt),
+            pending_post::citation::set(convert_and_sanitize(&citation_source)),
+            pending_post::excerpt_source::set(Some(excerpt_source)),
+            pending_post::citation_source::set(Some(citation_source)),
+        ],
+    )
+    .await
+    .map_err(|_| Status::InternalServerError)?;
+
+    let submitted = submit_draft(draft.id).await.map_err(|_| Status::InternalServerError)?;
+
+    webhooks::emit(Event::SubmissionCreated {
+        id: submitted.id.clone(),
+        section: submitted.category,
+        author_id: sub.to_string(),
+    });
+
+    Ok(Json(submitted))
+}
+
+/// Also deletes the draft's image files
+#[delete("/me/drafts?<id>")]
+pub async fn delete_draft(auth_header: AuthHeader, id: UuidField) -> Result<Value, Status> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    get_draft(sub.to_string(), id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let paths = remove_draft(id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // The draft is already gone, so a leftover file isn't worth failing over
+    for path in paths {
+        if let Err(e) = ImageField::remove_persisted(&path).await {
+            error!("Couldn't remove image {path} of deleted draft: {e}");
+        }
+    }
+
+    Ok(json!({ "id": id.to_string() }))
+}
+
+/// Drafts can be incomplete, the length requirements only apply once they're
+/// submitted
+#[derive(FromForm)]
+pub struct DraftForm {
+    /// Only used when editing, new drafts get the section from the path
+    pub(crate) section: Option<Category>,
+    /// Markdown
+    #[field(validate = len(..1500))]
+    pub(crate) excerpt: Option<String>,
+    #[field(validate = len(..200))]
+    pub(crate) citation: Option<String>,
+}
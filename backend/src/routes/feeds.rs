@@ -0,0 +1,191 @@
+use crate::{
+    db::{
+        prisma::Category,
+        util::{get_posts, get_posts_state, PostFilter, PostOrder},
+    },
+    feeds::{Feed, FeedFormat, FEED_SIZE},
+    routes::utils::{headers::ConditionalHeaders, misc::PaginationFields, responses::CachedResponse},
+};
+use chrono::{DateTime, FixedOffset};
+use rocket::{http::Status, request::FromParam};
+use sha2::{Digest, Sha256};
+
+/// A feed's file name, e.g. `islamism.atom` or `all.json`
+pub struct FeedFile {
+    section: Option<Category>,
+    format: FeedFormat,
+}
+
+impl<'a> FromParam<'a> for FeedFile {
+    type Error = &'a str;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        let (name, ext) = param.rsplit_once('.').ok_or(param)?;
+
+        let format = match ext {
+            "atom" => FeedFormat::Atom,
+            "rss" => FeedFormat::Rss,
+            "json" => FeedFormat::Json,
+            _ => return Err(param),
+        };
+
+        let section = match name {
+            "all" => None,
+            _ => Some(Category::from_param(name).map_err(|_| param)?),
+        };
+
+        Ok(FeedFile { section, format })
+    }
+}
+
+#[get("/feeds/<file>")]
+pub async fn get_feed(file: FeedFile, conditional: ConditionalHeaders) -> Result<CachedResponse, Status> {
+    let FeedFile { section, format } = file;
+
+    let (count, last_modified) = get_posts_state(section)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let etag = etag(section, format, count, last_modified);
+
+    if conditional.is_fresh(&etag, last_modified) {
+        return Ok(CachedResponse::NotModified { etag });
+    }
+
+    let pagination = PaginationFields {
+        page: 1,
+        per_page: FEED_SIZE,
+        cursor: None,
+    };
+
+    let filter = PostFilter {
+        category: section,
+        ..Default::default()
+    };
+
+    let posts = get_posts(filter, PostOrder::Newest, pagination)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .items;
+
+    Ok(CachedResponse::Fresh {
+        body: Feed::new(section, &posts).await.render(format),
+        content_type: format.content_type(),
+        etag,
+        last_modified,
+    })
+}
+
+/// Changes whenever a post is confirmed, edited or removed. Includes the
+/// surrounding quotes.
+fn etag(
+    section: Option<Category>,
+    format: FeedFormat,
+    count: i64,
+    last_modified: Option<DateTime<FixedOffset>>,
+) -> String {
+    let key = format!(
+        "{section:?}.{}.{count}.{}",
+        format.extension(),
+        last_modified.map(|t| t.timestamp_millis()).unwrap_or_default()
+    );
+
+    format!("\"{}\"", &hex::encode(Sha256::digest(key))[..32])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::{
+        http::{ContentType, Header},
+        local::blocking::Client,
+    };
+
+    fn at(s: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(s).unwrap()
+    }
+
+    fn conditional(if_none_match: Option<&str>, if_modified_since: Option<&str>) -> ConditionalHeaders {
+        ConditionalHeaders {
+            if_none_match: if_none_match.map(str::to_owned),
+            if_modified_since: if_modified_since.map(at),
+        }
+    }
+
+    #[test]
+    fn etag_changes_with_the_posts() {
+        let modified = Some(at("2023-05-01T12:00:00+00:00"));
+        let tag = etag(Some(Category::Islamism), FeedFormat::Atom, 3, modified);
+
+        assert!(tag.starts_with('"') && tag.ends_with('"'));
+        assert_eq!(tag, etag(Some(Category::Islamism), FeedFormat::Atom, 3, modified));
+        assert_ne!(tag, etag(Some(Category::Islamism), FeedFormat::Atom, 2, modified));
+        assert_ne!(
+            tag,
+            etag(
+                Some(Category::Islamism),
+                FeedFormat::Atom,
+                3,
+                Some(at("2023-05-01T12:00:01+00:00"))
+            )
+        );
+        assert_ne!(tag, etag(Some(Category::Islamism), FeedFormat::Rss, 3, modified));
+        assert_ne!(tag, etag(None, FeedFormat::Atom, 3, modified));
+    }
+
+    #[test]
+    fn if_none_match_compares_etags() {
+        assert!(conditional(Some(r#""abc""#), None).is_fresh(r#""abc""#, None));
+        assert!(conditional(Some(r#"W/"abc""#), None).is_fresh(r#""abc""#, None));
+        assert!(conditional(Some(r#""xyz", "abc""#), None).is_fresh(r#""abc""#, None));
+        assert!(conditional(Some("*"), None).is_fresh(r#""abc""#, None));
+        assert!(!conditional(Some(r#""xyz""#), None).is_fresh(r#""abc""#, None));
+        assert!(!conditional(None, None).is_fresh(r#""abc""#, None));
+    }
+
+    #[test]
+    fn if_modified_since_only_counts_without_if_none_match() {
+        let modified = Some(at("2023-05-01T12:00:00+00:00"));
+
+        assert!(conditional(None, Some("2023-05-01T12:00:00+00:00")).is_fresh(r#""abc""#, modified));
+        assert!(!conditional(None, Some("2023-05-01T11:59:59+00:00")).is_fresh(r#""abc""#, modified));
+        assert!(!conditional(Some(r#""xyz""#), Some("2023-05-01T12:00:00+00:00")).is_fresh(r#""abc""#, modified));
+    }
+
+    /// Stands in for [`get_feed`], which needs a database
+    #[get("/cached")]
+    fn cached(conditional: ConditionalHeaders) -> CachedResponse {
+        let etag = r#""v1""#.to_owned();
+
+        if conditional.is_fresh(&etag, None) {
+            return CachedResponse::NotModified { etag };
+        }
+
+        CachedResponse::Fresh {
+            body: "feed".to_owned(),
+            content_type: ContentType::Plain,
+            etag,
+            last_modified: None,
+        }
+    }
+
+    #[test]
+    fn current_copies_get_a_304() {
+        let client = Client::tracked(rocket::build().mount("/", routes![cached])).unwrap();
+
+        let fresh = client.get("/cached").dispatch();
+
+        assert_eq!(fresh.status(), Status::Ok);
+        assert_eq!(fresh.headers().get_one("ETag"), Some(r#""v1""#));
+        assert_eq!(fresh.into_string().as_deref(), Some("feed"));
+
+        let cached = client
+            .get("/cached")
+            .header(Header::new("If-None-Match", r#""v1""#))
+            .dispatch();
+
+        assert_eq!(cached.status(), Status::NotModified);
+        assert_eq!(cached.headers().get_one("ETag"), Some(r#""v1""#));
+        assert!(cached.into_string().unwrap_or_default().is_empty());
+    }
+}
@@ -1,8 +1,14 @@
+pub mod admin;
+pub mod collections;
+pub mod comments;
+pub mod drafts;
+pub mod feeds;
 pub mod notifications;
 pub mod posts;
+pub mod rejections;
+pub mod reports;
 pub mod sections;
 pub mod sign_in;
 pub mod sign_up;
-// TODO: Admin route to submit/delete images on posts
 pub mod submissions;
 pub mod utils;
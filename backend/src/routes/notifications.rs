@@ -1,16 +1,29 @@
 use crate::{
     db::{
+        hub,
+        prisma::{AuditAction, NotificationDelivery, NotificationType},
         util,
-        util::{get_user_notifications, update_user_notification, WhichNotifications},
+        util::{
+            count_user_notifications, get_user_notifications, get_user_notifications_after,
+            mark_all_user_notifications_read, update_user_notifications, WhichNotifications,
+        },
     },
     routes::utils::{
-        headers::{AuthHeader, Verifiable},
+        audit::{audit, snapshot},
+        headers::{AuthHeader, LastEventId, RequestMeta, Verifiable},
         jwt::Claims,
-        misc::PaginationFields,
+        misc::{PaginationFields, TimestampField},
         responses::NotificationBody,
     },
 };
-use rocket::{http::Status, serde::json::Json};
+use rocket::{
+    http::Status,
+    response::stream::{Event, EventStream},
+    serde::json::{json, Json, Value},
+    tokio::{select, sync::broadcast::error::RecvError},
+    Shutdown,
+};
+use std::collections::HashSet;
 use uuid::Uuid;
 
 #[get("/notifications?<which>&<pagination..>")]
@@ -27,13 +40,103 @@ pub async fn get_notifications(
             .await
             .map_err(|_| Status::InternalServerError)?
             .into_iter()
-            .map(NotificationBody::from)
-            .collect()
+            .map(NotificationBody::try_from)
+            .collect::<Result<_, _>>()
+            .map_err(|_| Status::InternalServerError)?
     };
 
     Ok(Json(notifs))
 }
 
+/// Pushes the user's notifications as they get created. Clients reconnecting
+/// with `Last-Event-ID` first receive whatever they missed in the meantime.
+#[get("/notifications/stream")]
+pub async fn notification_stream(
+    auth_header: AuthHeader,
+    last_event_id: LastEventId,
+    mut shutdown: Shutdown,
+) -> Result<EventStream![], Status> {
+    let Claims { sub: user, .. } = auth_header.verify()?;
+
+    // Subscribing before looking up missed notifications so that nothing
+    // created in between gets lost
+    let mut rx = hub::subscribe();
+
+    let missed = match last_event_id.0 {
+        Some(id) => get_user_notifications_after(user, id)
+            .await
+            .map_err(|_| Status::InternalServerError)?,
+        None => vec![],
+    };
+
+    let user = user.to_string();
+
+    Ok(EventStream! {
+        let mut sent = HashSet::with_capacity(missed.len());
+
+        for notif in missed {
+            sent.insert(notif.id.clone());
+
+            if let Ok(body) = NotificationBody::try_from(notif) {
+                yield notification_event(body);
+            }
+        }
+
+        loop {
+            let notif = select! {
+                notif = rx.recv() => match notif {
+                    Ok(notif) => notif,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+                _ = &mut shutdown => break,
+            };
+
+            if notif.recipient_id != user || sent.contains(&notif.id) {
+                continue;
+            }
+
+            if let Ok(body) = NotificationBody::try_from(notif) {
+                yield notification_event(body);
+            }
+        }
+    })
+}
+
+fn notification_event(body: NotificationBody) -> Event {
+    let id = body.id.clone();
+
+    Event::json(&body).id(id).event("notification")
+}
+
+#[get("/notifications/count?<which>")]
+pub async fn get_notification_count(
+    auth_header: AuthHeader,
+    which: Option<WhichNotifications>,
+) -> Result<Value, Status> {
+    let Claims { sub: user, .. } = auth_header.verify()?;
+
+    let count = count_user_notifications(user, which.unwrap_or(WhichNotifications::Unread))
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(json!({ "count": count }))
+}
+
+/// Marks every unread notification as read, or only those created before
+/// `before` if it's given, e.g. to leave ones that arrived after the list was
+/// rendered untouched.
+#[post("/notifications/read-all?<before>")]
+pub async fn read_all_notifications(auth_header: AuthHeader, before: Option<TimestampField>) -> Result<Value, Status> {
+    let Claims { sub: user, .. } = auth_header.verify()?;
+
+    let updated = mark_all_user_notifications_read(user, before.map(|b| b.0))
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(json!({ "updated": updated }))
+}
+
 // NOTE: Can't use patch on frontend because of form limitations
 #[patch("/notifications", data = "<patches>")]
 pub async fn patch_notifications(
@@ -42,37 +145,113 @@ pub async fn patch_notifications(
 ) -> Result<(), Status> {
     let Claims { sub: user, .. } = auth_header.verify()?;
 
-    for p in patches.iter() {
-        update_user_notification(user.to_string(), p.notification_id.to_string(), p.read)
-            .await
-            .map_err(|_| Status::InternalServerError)?;
-    }
+    let patches = patches
+        .iter()
+        .map(|p| (p.notification_id.to_string(), p.read))
+        .collect();
 
-    Ok(())
+    update_user_notifications(user.to_string(), patches)
+        .await
+        .map_err(|_| Status::InternalServerError)
 }
 
 #[delete("/notifications", data = "<form>")]
-pub async fn delete_notification(auth_header: AuthHeader, form: Json<DeleteNotificationForm>) -> Result<(), Status> {
-    let Claims { sub: user, admin, .. } = auth_header.verify()?;
+pub async fn delete_notification(
+    auth_header: AuthHeader,
+    meta: RequestMeta,
+    form: Json<DeleteNotificationForm>,
+) -> Result<(), Status> {
+    let claims = auth_header.verify()?;
+    let Claims { sub: user, admin, .. } = claims;
+
+    let ids = form.ids().map(|id| id.to_string()).collect::<Vec<_>>();
+
+    if ids.is_empty() {
+        return Err(Status::BadRequest);
+    }
 
     if !admin {
-        return util::delete_user_notification(user.to_string(), form.id.to_string())
+        return util::delete_user_notifications(user.to_string(), ids)
             .await
+            .map(|_| ())
             .map_err(|_| Status::InternalServerError);
     }
 
-    util::delete_notification(form.id.to_string())
+    let before = util::get_notifications_by_id(ids.clone())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    util::delete_notifications(ids.clone())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    for notif in before {
+        audit(
+            &claims,
+            meta.clone(),
+            AuditAction::DeleteNotifications,
+            notif.id.clone(),
+            snapshot(&notif),
+            None,
+        )
+        .await;
+    }
+
+    Ok(())
+}
+
+#[get("/notifications/preferences")]
+pub async fn get_notification_preferences(
+    auth_header: AuthHeader,
+) -> Result<Json<Vec<NotificationPreference>>, Status> {
+    let Claims { sub: user, .. } = auth_header.verify()?;
+
+    let prefs = util::get_notification_preferences(user.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .into_iter()
+        .map(|(n_type, delivery)| NotificationPreference { n_type, delivery })
+        .collect();
+
+    Ok(Json(prefs))
+}
+
+#[put("/notifications/preferences", data = "<prefs>")]
+pub async fn put_notification_preferences(
+    auth_header: AuthHeader,
+    prefs: Json<Vec<NotificationPreference>>,
+) -> Result<(), Status> {
+    let Claims { sub: user, .. } = auth_header.verify()?;
+
+    let prefs = prefs.iter().map(|p| (p.n_type, p.delivery)).collect();
+
+    util::set_notification_preferences(user.to_string(), prefs)
         .await
         .map_err(|_| Status::InternalServerError)
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct NotificationPreference {
+    n_type: NotificationType,
+    delivery: NotificationDelivery,
+}
+
 #[derive(Deserialize)]
 pub struct PatchNotificationBody {
     notification_id: Uuid,
     read: bool,
 }
 
+/// Accepts a single `id`, a list of `ids`, or both
 #[derive(Deserialize)]
 pub struct DeleteNotificationForm {
-    id: Uuid,
+    id: Option<Uuid>,
+    #[serde(default)]
+    ids: Vec<Uuid>,
+}
+
+impl DeleteNotificationForm {
+    fn ids(&self) -> impl Iterator<Item = &Uuid> {
+        self.id.iter().chain(self.ids.iter())
+    }
 }
@@ -1,25 +1,53 @@
 use crate::{
     db,
     db::{
-        prisma::{post, Category},
-        util::{get_user_posts, get_user_posts_in_section, remove_post},
+        prisma::{image, post, AuditAction, Category, ReactionKind},
+        util::{
+            count_post_images, create_image, create_notification, get_post_by_id, get_posts, get_published_post,
+            get_published_post_by_id, record_post_view, remove_image, remove_post, remove_reaction, set_reaction,
+            update_post_images, NotificationContent, PostFilter, PostOrder,
+        },
     },
-    routes::utils::{
-        headers::{AuthHeader, AuthLevel, Verifiable},
-        misc::{PaginationFields, UuidField},
+    routes::{
+        submissions::err_to_status,
+        utils::{
+            audit::{audit, snapshot},
+            headers::{AuthHeader, AuthLevel, RequestMeta, Verifiable},
+            jwt::Claims,
+            misc::{
+                sanitize_alt_text, sanitize_image_text, DateField, ImageField, ListingFields, PaginationFields,
+                UuidField,
+            },
+            responses::Paginated,
+        },
     },
+    webhooks::{self, Event},
 };
+use ammonia::clean;
+use chrono::Utc;
 use rocket::{
+    form::{Form, Strict},
     http::Status,
     serde::json::{json, Json, Value},
 };
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
 use uuid::Uuid;
+use validator::Validate;
 
+/// Posts scheduled for later are only found by admins until they're published
 #[get("/posts/<section>?<id>", rank = 1)]
-pub async fn get_post(section: Category, id: UuidField) -> Result<Json<post::Data>, Status> {
-    let post = db::util::get_post(section, id.to_string())
-        .await
-        .map_err(|_| Status::InternalServerError)?;
+pub async fn get_post(
+    auth_header: Option<AuthHeader<{ AuthLevel::Admin }>>,
+    section: Category,
+    id: UuidField,
+) -> Result<Json<post::Data>, Status> {
+    let post = if auth_header.and_then(|h| h.verify().ok()).is_some() {
+        db::util::get_post(section, id.to_string()).await
+    } else {
+        get_published_post(section, id.to_string()).await
+    }
+    .map_err(|_| Status::InternalServerError)?;
 
     if let Some(post) = post {
         Ok(Json(post))
@@ -28,61 +56,420 @@ pub async fn get_post(section: Category, id: UuidField) -> Result<Json<post::Dat
     }
 }
 
-#[get("/posts/<section>?<pagination..>", rank = 3)]
-pub async fn get_section_posts(
-    section: Category,
-    pagination: PaginationFields,
-) -> Result<Json<Vec<post::Data>>, Status> {
-    let posts = db::util::get_section_posts(section, pagination)
+/// A random post, from the section if one is given
+#[get("/posts/random?<section>&<seed>")]
+pub async fn get_random_post(section: Option<Category>, seed: Option<u64>) -> Result<Json<post::Data>, Status> {
+    let post = db::util::get_random_post(section, seed)
         .await
         .map_err(|_| Status::InternalServerError)?;
 
-    Ok(Json(posts))
+    post.map(Json).ok_or(Status::NotFound)
 }
 
-#[get("/posts?<author>&<pagination..>")]
-pub async fn get_author_posts(
-    author: UuidField,
+/// Approved posts that aren't published yet, the soonest first
+#[get("/admin/posts/scheduled?<pagination..>")]
+pub async fn get_scheduled_posts(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
     pagination: PaginationFields,
-) -> Result<Json<Vec<post::Data>>, Status> {
-    let posts = get_user_posts(author.to_string(), pagination)
+) -> Result<Paginated<post::Data>, Status> {
+    let _c = auth_header.verify()?;
+
+    let filter = PostFilter {
+        since: Some(Utc::now().into()),
+        include_scheduled: true,
+        ..Default::default()
+    };
+
+    let posts = get_posts(filter, PostOrder::Oldest, pagination)
         .await
         .map_err(|_| Status::InternalServerError)?;
 
-    Ok(Json(posts))
+    Ok(Paginated(posts))
 }
 
-#[get("/posts/<section>?<author>&<pagination..>", rank = 2)]
+/// The post featured on the date, today by default. Everyone gets the same one
+/// for a given day.
+#[get("/posts/daily?<section>&<date>")]
+pub async fn get_daily_post(section: Option<Category>, date: Option<DateField>) -> Result<Json<post::Data>, Status> {
+    let today = Utc::now().date_naive();
+    let date = date.map_or(today, |d| *d);
+
+    // Picking ahead of time would make the future picks depend on when they were
+    // asked for
+    if date > today {
+        return Err(Status::BadRequest);
+    }
+
+    let post = db::util::get_daily_post(section, date)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    post.map(Json).ok_or(Status::NotFound)
+}
+
+#[get("/posts/<section>?<listing..>", rank = 3)]
+pub async fn get_section_posts(section: Category, listing: ListingFields) -> Result<Paginated<post::Data>, Status> {
+    let filter = PostFilter {
+        category: Some(section),
+        ..listing.filter()
+    };
+
+    let posts = get_posts(filter, listing.order(), listing.pagination())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Paginated(posts))
+}
+
+#[get("/posts?<author>&<listing..>")]
+pub async fn get_author_posts(author: UuidField, listing: ListingFields) -> Result<Paginated<post::Data>, Status> {
+    let filter = PostFilter {
+        author_id: Some(author.to_string()),
+        ..listing.filter()
+    };
+
+    let posts = get_posts(filter, listing.order(), listing.pagination())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Paginated(posts))
+}
+
+#[get("/posts/<section>?<author>&<listing..>", rank = 2)]
 pub async fn get_author_section_posts(
     section: Category,
     author: UuidField,
-    pagination: PaginationFields,
-) -> Result<Json<Vec<post::Data>>, Status> {
-    let posts = get_user_posts_in_section(section, author.to_string(), pagination)
+    listing: ListingFields,
+) -> Result<Paginated<post::Data>, Status> {
+    let filter = PostFilter {
+        category: Some(section),
+        author_id: Some(author.to_string()),
+        ..listing.filter()
+    };
+
+    let posts = get_posts(filter, listing.order(), listing.pagination())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Paginated(posts))
+}
+
+/// Replaces the user's previous reaction to the post, if they had one
+#[put("/posts/reactions", data = "<body>")]
+pub async fn put_reaction(auth_header: AuthHeader, body: Json<ReactionBody>) -> Result<Value, Status> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    get_published_post_by_id(body.id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    set_reaction(sub.to_string(), body.id.to_string(), body.kind)
         .await
         .map_err(|_| Status::InternalServerError)?;
 
-    Ok(Json(posts))
+    Ok(json!({ "id": body.id, "kind": body.kind }))
+}
+
+#[delete("/posts/reactions", data = "<body>")]
+pub async fn delete_reaction(auth_header: AuthHeader, body: Json<ReactionDeletionBody>) -> Result<Value, Status> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    let removed = remove_reaction(sub.to_string(), body.id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if !removed {
+        return Err(Status::NotFound);
+    }
+
+    Ok(json!({ "id": body.id }))
+}
+
+/// Meant to be called once the post has actually been shown. Signing in is
+/// optional, anonymous views are told apart by IP address.
+#[post("/posts/views", data = "<body>")]
+pub async fn new_post_view(
+    auth_header: Option<AuthHeader>,
+    client_ip: Option<IpAddr>,
+    body: Json<PostViewBody>,
+) -> Result<Value, Status> {
+    let viewer = match (auth_header.and_then(|h| h.verify().ok()), client_ip) {
+        (Some(Claims { sub, .. }), _) => format!("user:{sub}"),
+        (None, Some(ip)) => format!("ip:{ip}"),
+        (None, None) => return Err(Status::BadRequest),
+    };
+
+    // Stored hashed, so IP addresses don't end up in the database
+    let viewer = hex::encode(Sha256::digest(viewer));
+
+    get_published_post_by_id(body.id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let counted = record_post_view(body.id.to_string(), viewer)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(json!({ "id": body.id, "counted": counted }))
+}
+
+#[derive(Deserialize)]
+pub struct ReactionBody {
+    /// The post's ID
+    pub(crate) id: Uuid,
+    pub(crate) kind: ReactionKind,
+}
+
+#[derive(Deserialize)]
+pub struct ReactionDeletionBody {
+    pub(crate) id: Uuid,
+}
+
+#[derive(Deserialize)]
+pub struct PostViewBody {
+    pub(crate) id: Uuid,
 }
 
 #[delete("/posts/<section>", data = "<post>")]
 pub async fn delete_post(
     auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    meta: RequestMeta,
     section: Category,
     post: Json<PostDeletionBody>,
 ) -> Result<Value, Status> {
-    let _c = auth_header.verify()?;
+    let claims = auth_header.verify()?;
 
     let id = post.id.to_string();
 
+    let data = db::util::get_post(section, id.clone())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let before = snapshot(&data);
+
+    discard_post(data, post.comment.clone()).await?;
+
+    audit(&claims, meta, AuditAction::DeletePost, id.clone(), before, None).await;
+
+    Ok(json!({ "id": id }))
+}
+
+/// Removes the post and its image files, then lets the author and webhooks
+/// know. `post` needs its images fetched.
+pub(crate) async fn discard_post(post: post::Data, comment: Option<String>) -> Result<(), Status> {
+    let post::Data {
+        id,
+        category: section,
+        author_id,
+        excerpt,
+        citation,
+        images,
+        ..
+    } = post;
+
     remove_post(section, id.clone())
         .await
         .map_err(|_| Status::InternalServerError)?;
 
-    Ok(json!({ "id": id }))
+    // The post is already gone, so a leftover file isn't worth failing over
+    for image in images.unwrap_or_default() {
+        if let Err(e) = ImageField::remove_persisted(&image.path).await {
+            error!("Couldn't remove image {} of deleted post: {e}", image.path);
+        }
+    }
+
+    let notif = NotificationContent::PostDeleted {
+        comment: comment.clone(),
+        excerpt,
+        citation,
+    };
+
+    create_notification(author_id, &notif)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    webhooks::emit(Event::PostDeleted { id, section, comment });
+
+    Ok(())
 }
 
 #[derive(Deserialize)]
 pub struct PostDeletionBody {
     pub(crate) id: Uuid,
+    /// Passed on to the author
+    pub(crate) comment: Option<String>,
+}
+
+#[post("/posts/images", data = "<form>")]
+pub async fn new_post_image(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    form: Form<Strict<PostImageForm>>,
+) -> Result<Json<image::Data>, Status> {
+    let _c = auth_header.verify()?;
+
+    let post_id = form.post_id.to_string();
+
+    if get_post_by_id(post_id.clone())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .is_none()
+    {
+        return Err(Status::NotFound);
+    }
+
+    let order = match form.order {
+        Some(order) => i32::try_from(order).map_err(|_| Status::BadRequest)?,
+        // Appended after the existing images by default
+        None => {
+            let count = count_post_images(post_id.clone())
+                .await
+                .map_err(|_| Status::InternalServerError)?;
+
+            i32::try_from(count).map_err(|_| Status::InternalServerError)?
+        }
+    };
+
+    let image = {
+        let width = i32::try_from(form.image.width).map_err(|_| Status::BadRequest)?;
+        let height = i32::try_from(form.image.height).map_err(|_| Status::BadRequest)?;
+        let alt_text = sanitize_alt_text(form.alt_text.as_str()).ok_or(Status::BadRequest)?;
+        let transcription = form.transcription.as_deref().and_then(sanitize_image_text);
+        let caption = sanitize_caption(form.caption.as_deref());
+        let path = form.image.persist().map_err(|_| Status::InternalServerError)?;
+
+        match create_image(
+            post_id,
+            path.clone(),
+            width,
+            height,
+            alt_text,
+            transcription,
+            order,
+            caption,
+        )
+        .await
+        {
+            Ok(image) => image,
+            Err(_) => {
+                // Nothing would refer to the file without its row
+                if let Err(e) = ImageField::remove_persisted(&path).await {
+                    error!("Couldn't remove image {path} after failing to save it: {e}");
+                }
+
+                return Err(Status::InternalServerError);
+            }
+        }
+    };
+
+    Ok(Json(image))
+}
+
+#[patch("/posts/images", data = "<body>")]
+pub async fn patch_post_images(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    body: Json<PatchPostImagesBody>,
+) -> Result<(), Status> {
+    let _c = auth_header.verify()?;
+
+    body.validate().map_err(|_| Status::BadRequest)?;
+
+    let mut patches = Vec::with_capacity(body.images.len());
+
+    for p in body.images.iter() {
+        let mut params = vec![];
+
+        if let Some(order) = p.order {
+            params.push(image::order::set(i32::try_from(order).map_err(|_| Status::BadRequest)?));
+        }
+
+        if let Some(caption) = &p.caption {
+            params.push(image::caption::set(sanitize_caption(Some(caption.as_str()))));
+        }
+
+        if let Some(alt_text) = &p.alt_text {
+            let alt_text = sanitize_alt_text(alt_text.as_str()).ok_or(Status::BadRequest)?;
+
+            params.push(image::alt_text::set(alt_text));
+        }
+
+        if let Some(transcription) = &p.transcription {
+            params.push(image::transcription::set(sanitize_image_text(transcription.as_str())));
+        }
+
+        patches.push((p.path.clone(), params));
+    }
+
+    update_post_images(body.post_id.to_string(), patches)
+        .await
+        .map_err(err_to_status)?;
+
+    Ok(())
+}
+
+#[delete("/posts/images", data = "<body>")]
+pub async fn delete_post_image(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    body: Json<ImageDeletionBody>,
+) -> Result<Value, Status> {
+    let _c = auth_header.verify()?;
+
+    let deleted = remove_image(body.path.clone())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if deleted == 0 {
+        return Err(Status::NotFound);
+    }
+
+    ImageField::remove_persisted(body.path.as_str())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(json!({ "path": body.path }))
+}
+
+/// Empty captions are treated as removing the caption
+fn sanitize_caption(caption: Option<&str>) -> Option<String> {
+    caption.map(str::trim).filter(|c| !c.is_empty()).map(clean)
+}
+
+#[derive(FromForm)]
+pub struct PostImageForm {
+    pub(crate) post_id: UuidField,
+    pub(crate) image: ImageField,
+    #[field(validate = len(1..1000))]
+    pub(crate) alt_text: String,
+    pub(crate) transcription: Option<String>,
+    #[field(validate = len(..1000))]
+    pub(crate) caption: Option<String>,
+    pub(crate) order: Option<u32>,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct PatchPostImagesBody {
+    #[serde(rename = "id")]
+    pub(crate) post_id: Uuid,
+    #[validate]
+    pub(crate) images: Vec<PatchImageBody>,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct PatchImageBody {
+    pub(crate) path: String,
+    pub(crate) order: Option<u32>,
+    /// An empty string clears the caption
+    #[validate(length(max = 1000))]
+    pub(crate) caption: Option<String>,
+    #[validate(length(min = 1, max = 1000))]
+    pub(crate) alt_text: Option<String>,
+    /// An empty string clears the transcription
+    pub(crate) transcription: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ImageDeletionBody {
+    pub(crate) path: String,
 }
@@ -0,0 +1,280 @@
+use crate::{
+    db::{
+        prisma::{canned_response, rejection_reason},
+        util,
+        util::RejectionStats,
+    },
+    routes::utils::{
+        headers::{AuthHeader, AuthLevel, Verifiable},
+        misc::{TimestampField, UuidField},
+    },
+};
+use rocket::{http::Status, serde::json::Json};
+use sanitizer::prelude::*;
+use validator::Validate;
+
+/// Placeholders that canned responses can use
+pub const PLACEHOLDERS: [&str; 4] = ["author", "section", "reasons", "citation"];
+
+/// Archived reasons are included, but can't be used for new rejections
+#[get("/admin/rejection-reasons")]
+pub async fn get_rejection_reasons(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+) -> Result<Json<Vec<rejection_reason::Data>>, Status> {
+    let _c = auth_header.verify()?;
+
+    let reasons = util::get_rejection_reasons()
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(reasons))
+}
+
+#[post("/admin/rejection-reasons", data = "<reason>")]
+pub async fn new_rejection_reason(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    reason: Json<RejectionReasonBody>,
+) -> Result<Json<rejection_reason::Data>, Status> {
+    let _c = auth_header.verify()?;
+
+    let mut reason = reason.into_inner();
+
+    reason.sanitize();
+    reason.validate().map_err(|_| Status::BadRequest)?;
+
+    if !reason.code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(Status::BadRequest);
+    }
+
+    let description = reason.description.as_deref().map(str::trim).filter(|d| !d.is_empty());
+
+    let exists = util::get_rejection_reasons()
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .iter()
+        .any(|r| r.code == reason.code);
+
+    if exists {
+        return Err(Status::Conflict);
+    }
+
+    let reason = util::create_rejection_reason(reason.code, reason.label, description.map(str::to_owned))
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(reason))
+}
+
+/// Codes can't be changed, since past rejections refer to them
+#[patch("/admin/rejection-reasons", data = "<patch>")]
+pub async fn patch_rejection_reason(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    patch: Json<RejectionReasonPatch>,
+) -> Result<Json<rejection_reason::Data>, Status> {
+    let _c = auth_header.verify()?;
+
+    patch.validate().map_err(|_| Status::BadRequest)?;
+
+    let mut params = vec![];
+
+    if let Some(label) = patch.label.as_deref().map(str::trim) {
+        if label.is_empty() {
+            return Err(Status::BadRequest);
+        }
+
+        params.push(rejection_reason::label::set(label.to_owned()));
+    }
+
+    if let Some(description) = patch.description.as_deref().map(str::trim) {
+        params.push(rejection_reason::description::set(
+            Some(description.to_owned()).filter(|d| !d.is_empty()),
+        ));
+    }
+
+    if let Some(archived) = patch.archived {
+        params.push(rejection_reason::archived::set(archived));
+    }
+
+    let reason = util::update_rejection_reason(patch.code.clone(), params)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(reason))
+}
+
+#[get("/admin/canned-responses")]
+pub async fn get_canned_responses(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+) -> Result<Json<Vec<canned_response::Data>>, Status> {
+    let _c = auth_header.verify()?;
+
+    let responses = util::get_canned_responses()
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(responses))
+}
+
+/// Fails if the body uses a placeholder other than the [`PLACEHOLDERS`]
+#[post("/admin/canned-responses", data = "<response>")]
+pub async fn new_canned_response(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    response: Json<CannedResponseBody>,
+) -> Result<Json<canned_response::Data>, Status> {
+    let _c = auth_header.verify()?;
+
+    let mut response = response.into_inner();
+
+    response.sanitize();
+    response.validate().map_err(|_| Status::BadRequest)?;
+
+    if !uses_known_placeholders(&response.body) {
+        return Err(Status::BadRequest);
+    }
+
+    let response = util::create_canned_response(response.name, response.body)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(response))
+}
+
+#[patch("/admin/canned-responses?<id>", data = "<response>")]
+pub async fn patch_canned_response(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    id: UuidField,
+    response: Json<CannedResponseBody>,
+) -> Result<Json<canned_response::Data>, Status> {
+    let _c = auth_header.verify()?;
+
+    let mut response = response.into_inner();
+
+    response.sanitize();
+    response.validate().map_err(|_| Status::BadRequest)?;
+
+    if !uses_known_placeholders(&response.body) {
+        return Err(Status::BadRequest);
+    }
+
+    let params = vec![
+        canned_response::name::set(response.name),
+        canned_response::body::set(response.body),
+    ];
+
+    let response = util::update_canned_response(id.to_string(), params)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(response))
+}
+
+#[delete("/admin/canned-responses?<id>")]
+pub async fn delete_canned_response(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    id: UuidField,
+) -> Result<(), Status> {
+    let _c = auth_header.verify()?;
+
+    let deleted = util::remove_canned_response(id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if deleted == 0 {
+        return Err(Status::NotFound);
+    }
+
+    Ok(())
+}
+
+/// Counts rejections between `since` and `until`, or of all time without them
+#[get("/admin/stats/rejections?<since>&<until>")]
+pub async fn get_rejection_stats(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    since: Option<TimestampField>,
+    until: Option<TimestampField>,
+) -> Result<Json<RejectionStats>, Status> {
+    let _c = auth_header.verify()?;
+
+    let stats = util::get_rejection_stats(since.map(|t| *t), until.map(|t| *t))
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(stats))
+}
+
+/// Replaces each `{name}` in the template with its value. Values are inserted
+/// as is, so placeholders inside them are left alone.
+pub(crate) fn fill_placeholders(template: &str, values: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let value = rest
+            .find('}')
+            .and_then(|end| values.iter().find(|(k, _)| *k == &rest[1..end]).map(|v| (end, v.1)));
+
+        match value {
+            Some((end, v)) => {
+                out.push_str(v);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    out.push_str(rest);
+
+    out
+}
+
+/// Whether every `{name}` in the body is one of the [`PLACEHOLDERS`]
+fn uses_known_placeholders(body: &str) -> bool {
+    body.split('{')
+        .skip(1)
+        .filter_map(|s| s.split_once('}'))
+        .all(|(name, _)| PLACEHOLDERS.contains(&name))
+}
+
+#[derive(Deserialize, Validate, Sanitize)]
+pub struct RejectionReasonBody {
+    /// Lowercase letters, digits and dashes, e.g. `missing-source`
+    #[sanitize(trim, lower_case)]
+    #[validate(length(min = 1, max = 64))]
+    pub(crate) code: String,
+    #[sanitize(trim)]
+    #[validate(length(min = 1, max = 200))]
+    pub(crate) label: String,
+    #[validate(length(max = 2000))]
+    pub(crate) description: Option<String>,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct RejectionReasonPatch {
+    pub(crate) code: String,
+    #[validate(length(min = 1, max = 200))]
+    pub(crate) label: Option<String>,
+    /// Empty to remove the description
+    #[validate(length(max = 2000))]
+    pub(crate) description: Option<String>,
+    /// Archived reasons can't be used for new rejections
+    pub(crate) archived: Option<bool>,
+}
+
+#[derive(Deserialize, Validate, Sanitize)]
+pub struct CannedResponseBody {
+    #[sanitize(trim)]
+    #[validate(length(min = 1, max = 100))]
+    pub(crate) name: String,
+    /// Can use the [`PLACEHOLDERS`] in braces, e.g. `{author}`
+    #[sanitize(trim)]
+    #[validate(length(min = 1, max = 5000))]
+    pub(crate) body: String,
+}
@@ -0,0 +1,268 @@
+use crate::{
+    db,
+    db::{
+        prisma::{post, report, AuditAction, CommentStatus, ReportAction, ReportReason},
+        util::{
+            claim_report, create_notification, file_report, finish_report, get_comment, get_post_by_id,
+            get_published_post_by_id, get_report, get_reports, remove_comment, reopen_report, update_post,
+            NotificationContent, ReportTarget,
+        },
+    },
+    routes::{
+        posts::discard_post,
+        utils::{
+            audit::{audit, snapshot},
+            headers::{AuthHeader, AuthLevel, RequestMeta, Verifiable},
+            jwt::Claims,
+            misc::{convert_and_sanitize, PaginationFields},
+        },
+    },
+};
+use ammonia::clean;
+use rocket::{http::Status, serde::json::Json};
+use uuid::Uuid;
+use validator::Validate;
+
+/// Reports of the same post or comment are grouped until an admin resolves
+/// them, so the queue only has one entry per target
+#[post("/reports", data = "<body>")]
+pub async fn new_report(auth_header: AuthHeader, body: Json<ReportBody>) -> Result<Json<report::Data>, Status> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    body.validate().map_err(|_| Status::BadRequest)?;
+
+    let target = match (body.post_id, body.comment_id) {
+        (Some(post_id), None) => {
+            get_published_post_by_id(post_id.to_string())
+                .await
+                .map_err(|_| Status::InternalServerError)?
+                .ok_or(Status::NotFound)?;
+
+            ReportTarget::Post(post_id.to_string())
+        }
+        (None, Some(comment_id)) => {
+            get_comment(comment_id.to_string())
+                .await
+                .map_err(|_| Status::InternalServerError)?
+                .filter(|c| c.status == CommentStatus::Visible)
+                .ok_or(Status::NotFound)?;
+
+            ReportTarget::Comment(comment_id.to_string())
+        }
+        _ => return Err(Status::BadRequest),
+    };
+
+    let details = body
+        .details
+        .as_deref()
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .map(clean);
+
+    let report = file_report(sub.to_string(), target, body.reason, details)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(report))
+}
+
+/// Open reports oldest first, or the resolved ones with `resolved=true`
+#[get("/admin/reports?<resolved>&<pagination..>")]
+pub async fn get_report_queue(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    resolved: Option<bool>,
+    pagination: PaginationFields,
+) -> Result<Json<Vec<report::Data>>, Status> {
+    let _c = auth_header.verify()?;
+
+    let reports = get_reports(resolved.unwrap_or(false), pagination)
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(reports))
+}
+
+/// Closes the report, carries out the action on the reported post or comment
+/// and notifies everyone who filed it. The report is opened again if the
+/// action fails.
+#[patch("/admin/reports", data = "<body>")]
+pub async fn resolve_report_action(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    meta: RequestMeta,
+    body: Json<ReportResolutionBody>,
+) -> Result<Json<report::Data>, Status> {
+    let claims = auth_header.verify()?;
+
+    body.validate().map_err(|_| Status::BadRequest)?;
+
+    let report = get_report(body.id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .filter(|r| r.resolved_at.is_none())
+        .ok_or(Status::NotFound)?;
+
+    // The target was deleted some other way, nothing left but to dismiss
+    if report.post_id.is_none() && report.comment_id.is_none() && body.action != ReportAction::Dismiss {
+        return Err(Status::Conflict);
+    }
+
+    let note = body.note.as_deref().map(str::trim).filter(|n| !n.is_empty()).map(clean);
+
+    // Claimed first, so two admins resolving it at once don't both act on it
+    let claimed = claim_report(report.id.clone(), claims.sub.to_string(), body.action, note.clone())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if !claimed {
+        return Err(Status::Conflict);
+    }
+
+    let acted = match (&report.post_id, &report.comment_id) {
+        (Some(post_id), _) => act_on_post(post_id.clone(), &body, note).await,
+        (None, Some(comment_id)) => act_on_comment(comment_id.clone(), body.action).await,
+        (None, None) => Ok(None),
+    };
+
+    let url = match acted {
+        Ok(url) => url,
+        Err(status) => {
+            if let Err(e) = reopen_report(&report).await {
+                error!("Couldn't reopen report {} after failing to act on it: {e}", report.id);
+            }
+
+            return Err(status);
+        }
+    };
+
+    let before = snapshot(&report);
+
+    let report = finish_report(report.id, url)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    audit(
+        &claims,
+        meta,
+        AuditAction::ResolveReport,
+        report.id.clone(),
+        before,
+        snapshot(&report),
+    )
+    .await;
+
+    Ok(Json(report))
+}
+
+/// Returns the post's URL if it's still around afterwards
+async fn act_on_post(id: String, body: &ReportResolutionBody, note: Option<String>) -> Result<Option<String>, Status> {
+    let post = get_post_by_id(id.clone())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    match body.action {
+        ReportAction::Dismiss => Ok(Some(post_url(&post))),
+        ReportAction::Edit => {
+            let mut params = vec![];
+
+            if let Some(excerpt) = body.excerpt.as_deref().map(str::trim) {
+                params.push(post::excerpt::set(convert_and_sanitize(excerpt)));
+                params.push(post::excerpt_source::set(Some(excerpt.to_owned())));
+            }
+
+            if let Some(citation) = body.citation.as_deref().map(str::trim) {
+                params.push(post::citation::set(convert_and_sanitize(citation)));
+                params.push(post::citation_source::set(Some(citation.to_owned())));
+            }
+
+            if params.is_empty() {
+                return Err(Status::BadRequest);
+            }
+
+            let post = update_post(id, params).await.map_err(|_| Status::InternalServerError)?;
+            let url = post_url(&post);
+
+            create_notification(
+                post.author_id,
+                &NotificationContent::PostEdited {
+                    url: url.clone(),
+                    comment: note,
+                },
+            )
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+
+            Ok(Some(url))
+        }
+        ReportAction::Delete => {
+            // Fetched again for its images, which have files to clean up
+            let post = db::util::get_post(post.category, id)
+                .await
+                .map_err(|_| Status::InternalServerError)?
+                .ok_or(Status::NotFound)?;
+
+            discard_post(post, note).await?;
+
+            Ok(None)
+        }
+    }
+}
+
+/// Comments can't be edited by admins, only dismissed or removed
+async fn act_on_comment(id: String, action: ReportAction) -> Result<Option<String>, Status> {
+    let comment = get_comment(id.clone())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    match action {
+        ReportAction::Dismiss => {
+            let post = get_post_by_id(comment.post_id)
+                .await
+                .map_err(|_| Status::InternalServerError)?
+                .ok_or(Status::NotFound)?;
+
+            Ok(Some(format!("{}#comment-{id}", post_url(&post))))
+        }
+        ReportAction::Edit => Err(Status::BadRequest),
+        ReportAction::Delete => {
+            remove_comment(id).await.map_err(|_| Status::InternalServerError)?;
+
+            Ok(None)
+        }
+    }
+}
+
+fn post_url(post: &post::Data) -> String {
+    format!(
+        "/posts/{}?id={}",
+        post.category.to_string().to_ascii_lowercase(),
+        post.id
+    )
+}
+
+#[derive(Deserialize, Validate)]
+pub struct ReportBody {
+    /// Exactly one of `post_id` and `comment_id` has to be set
+    pub(crate) post_id: Option<Uuid>,
+    pub(crate) comment_id: Option<Uuid>,
+    pub(crate) reason: ReportReason,
+    #[validate(length(max = 2000))]
+    pub(crate) details: Option<String>,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct ReportResolutionBody {
+    pub(crate) id: Uuid,
+    pub(crate) action: ReportAction,
+    /// Passed on to the reporters, and to the author when editing or deleting
+    /// a post
+    #[validate(length(max = 2000))]
+    pub(crate) note: Option<String>,
+    /// Markdown, only used when editing a post
+    #[validate(length(min = 10, max = 1500))]
+    pub(crate) excerpt: Option<String>,
+    #[validate(length(min = 10, max = 200))]
+    pub(crate) citation: Option<String>,
+}
@@ -4,6 +4,7 @@ use crate::{
         misc::{sanitize_and_validate, validate_username},
         responses::LoginResponse,
     },
+    webhooks::{self, Event},
 };
 use rocket::{
     form::{Form, Strict},
@@ -21,9 +22,19 @@ pub async fn sign_up(signup: Form<Strict<SignUpForm>>) -> Result<LoginResponse,
         None => return Err(Status::BadRequest),
     };
 
+    let id = Uuid::new_v4();
+    let username = signup.username.clone();
+
     // TODO: Improve error handling, notify if username is taken
-    match create_user(Uuid::new_v4(), signup.username, signup.password).await {
-        Ok(token) => Ok(LoginResponse { token }),
+    match create_user(id, signup.username, signup.password).await {
+        Ok(token) => {
+            webhooks::emit(Event::UserSignedUp {
+                id: id.to_string(),
+                username,
+            });
+
+            Ok(LoginResponse { token })
+        }
         Err(e) => {
             dbg!(e);
             Err(Status::InternalServerError)
@@ -1,25 +1,39 @@
 use crate::{
     db::{
-        prisma::{pending_image, pending_post, Category},
+        self,
+        prisma::{notification, pending_image, pending_post, AuditAction, Category, Role, SubmissionStatus},
         util::{
-            confirm_pending_post, create_pending_image, create_pending_post, get_pending_post, get_pending_post_by_id,
-            get_section_pending_posts, get_user_pending_posts, get_user_pending_posts_in_section, reject_pending_post,
+            assign_pending_post, check_submission, check_upload, claim_pending_post, confirm_pending_post,
+            create_pending_image, create_pending_post, find_similar_excerpts, get_active_rejection_reasons,
+            get_canned_response, get_claimed_pending_posts, get_pending_post, get_pending_post_by_id,
+            get_pending_posts, get_user_by_id, reject_pending_post, release_pending_post, update_draft, Confirmation,
+            PostFilter, SimilarExcerpt,
         },
     },
-    routes::utils::{
-        headers::{AuthHeader, AuthLevel, Verifiable},
-        misc::{ImageField, PaginationFields, UuidField},
-        responses::NotificationBody,
+    routes::{
+        rejections::fill_placeholders,
+        utils::{
+            audit::{audit, snapshot},
+            headers::{AuthHeader, AuthLevel, RequestMeta, Verifiable},
+            jwt::Claims,
+            misc::{
+                convert_and_sanitize, html_to_text, sanitize_alt_text, sanitize_image_text, ImageField,
+                ImageMetadataEdit, ListingFields, TimestampField, UuidField,
+            },
+            responses::{NotificationBody, Paginated, SubmissionError},
+        },
     },
+    similarity::DUPLICATE_THRESHOLD,
+    webhooks::{self, Event},
 };
 use ammonia::clean;
+use chrono::Utc;
 use prisma_client_rust::QueryError;
-use pulldown_cmark::{html, Parser};
 use rocket::{
     form::{Form, Strict},
     http::Status,
     response::Responder,
-    serde::json::Json,
+    serde::json::{json, Json, Value},
     Request, Response,
 };
 use sanitizer::prelude::*;
@@ -32,82 +46,123 @@ pub async fn get_submission(
     auth_header: AuthHeader<{ AuthLevel::Admin }>,
     section: Category,
     id: UuidField,
-) -> Result<Json<pending_post::Data>, Status> {
+) -> Result<Json<SubmissionBody>, Status> {
     let _c = auth_header.verify()?;
 
-    let ret = get_pending_post(section, id.to_string())
+    let submission = get_pending_post(section, id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let similar = find_similar_excerpts(&submission.excerpt, submission.id.clone())
         .await
         .map_err(|_| Status::InternalServerError)?;
 
-    if let Some(ret) = ret {
-        Ok(Json(ret))
-    } else {
-        Err(Status::NotFound)
-    }
+    Ok(Json(SubmissionBody { submission, similar }))
 }
 
-#[get("/submissions/<section>?<pagination..>")]
+/// Every submission shows who's reviewing it in `reviewerId`, until
+/// `claimedUntil`. With `unclaimed=true`, only those nobody is reviewing.
+#[get("/submissions/<section>?<unclaimed>&<listing..>")]
 pub async fn get_section_submissions(
     auth_header: AuthHeader<{ AuthLevel::Admin }>,
     section: Category,
-    pagination: PaginationFields,
-) -> Result<Json<Vec<pending_post::Data>>, Status> {
+    unclaimed: Option<bool>,
+    listing: ListingFields,
+) -> Result<Paginated<pending_post::Data>, Status> {
     let _c = auth_header.verify()?;
 
-    let pending_posts = get_section_pending_posts(section, pagination)
+    let filter = PostFilter {
+        category: Some(section),
+        unclaimed: unclaimed.unwrap_or(false),
+        ..listing.filter()
+    };
+
+    let pending_posts = get_pending_posts(filter, listing.order(), listing.pagination())
         .await
         .map_err(|_| Status::InternalServerError)?;
 
-    Ok(Json(pending_posts))
+    Ok(Paginated(pending_posts))
 }
 
-#[get("/submissions?<author>&<pagination..>")]
+#[get("/submissions?<author>&<listing..>")]
 pub async fn get_author_submissions(
     auth_header: AuthHeader<{ AuthLevel::Admin }>,
     author: UuidField,
-    pagination: PaginationFields,
-) -> Result<Json<Vec<pending_post::Data>>, Status> {
+    listing: ListingFields,
+) -> Result<Paginated<pending_post::Data>, Status> {
     let _c = auth_header.verify()?;
 
-    let posts = get_user_pending_posts(author.to_string(), pagination)
+    let filter = PostFilter {
+        author_id: Some(author.to_string()),
+        ..listing.filter()
+    };
+
+    let posts = get_pending_posts(filter, listing.order(), listing.pagination())
         .await
         .map_err(|_| Status::InternalServerError)?;
 
-    Ok(Json(posts))
+    Ok(Paginated(posts))
 }
 
-#[get("/submissions/<section>?<author>&<pagination..>", rank = 2)]
+#[get("/submissions/<section>?<author>&<listing..>", rank = 2)]
 pub async fn get_author_section_submissions(
     auth_header: AuthHeader<{ AuthLevel::Admin }>,
     section: Category,
     author: UuidField,
-    pagination: PaginationFields,
-) -> Result<Json<Vec<pending_post::Data>>, Status> {
+    listing: ListingFields,
+) -> Result<Paginated<pending_post::Data>, Status> {
     let _c = auth_header.verify()?;
 
-    let posts = get_user_pending_posts_in_section(section, author.to_string(), pagination)
+    let filter = PostFilter {
+        category: Some(section),
+        author_id: Some(author.to_string()),
+        ..listing.filter()
+    };
+
+    let posts = get_pending_posts(filter, listing.order(), listing.pagination())
         .await
         .map_err(|_| Status::InternalServerError)?;
 
-    Ok(Json(posts))
+    Ok(Paginated(posts))
 }
 
-/// Returns the new post's [`Uuid`]
+/// Returns the new post's [`Uuid`], or why the author can't submit it
 #[post("/submissions/<section>/submit", data = "<post>")]
 pub async fn new_submission(
     auth_header: AuthHeader,
     section: Category,
     mut post: Form<Strict<PostSubmissionForm>>,
-) -> Result<PostSubmissionResponse, Status> {
+) -> Result<PostSubmissionResponse, SubmissionError> {
     let c = auth_header.verify()?;
 
     post.sanitize();
 
+    if let Some(violation) = check_submission(c.sub.to_string(), &convert_and_sanitize(&post.excerpt))
+        .await
+        .map_err(|_| Status::InternalServerError)?
+    {
+        return Err(SubmissionError::Refused(violation));
+    }
+
     let id = Uuid::new_v4();
 
-    create_pending_post(section, id, c.sub, post.excerpt.clone(), post.citation.clone())
-        .await
-        .map_err(|_| Status::InternalServerError)?;
+    create_pending_post(
+        section,
+        id,
+        c.sub,
+        post.excerpt.clone(),
+        post.citation.clone(),
+        SubmissionStatus::Queued,
+    )
+    .await
+    .map_err(|_| Status::InternalServerError)?;
+
+    webhooks::emit(Event::SubmissionCreated {
+        id: id.to_string(),
+        section,
+        author_id: c.sub.to_string(),
+    });
 
     Ok(PostSubmissionResponse { id: id.to_string() })
 }
@@ -116,109 +171,403 @@ pub async fn new_submission(
 pub async fn new_submission_image(
     auth_header: AuthHeader,
     form: Form<Strict<ImageSubmissionForm>>,
-) -> Result<Json<pending_image::Data>, Status> {
+) -> Result<Json<pending_image::Data>, SubmissionError> {
     let c = auth_header.verify()?;
 
-    match get_pending_post_by_id(form.post_id.to_string())
+    let status = match get_pending_post_by_id(form.post_id.to_string())
         .await
         .map_err(|_| Status::InternalServerError)?
     {
-        Some(pending_post::Data { author_id, .. }) => {
+        Some(pending_post::Data { author_id, status, .. }) => {
             if !c.admin {
                 let author_id = Uuid::from_str(author_id.as_str()).map_err(|_| Status::InternalServerError)?;
 
                 if author_id != c.sub {
-                    return Err(Status::Unauthorized);
+                    return Err(Status::Unauthorized.into());
                 }
             }
+
+            status
         }
-        None => return Err(Status::NotFound),
+        None => return Err(Status::NotFound.into()),
+    };
+
+    if let Some(violation) = check_upload(c.sub.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+    {
+        return Err(SubmissionError::Refused(violation));
     }
 
     let pending_image = {
         let width = i32::try_from(form.image.width).map_err(|_| Status::BadRequest)?;
         let height = i32::try_from(form.image.height).map_err(|_| Status::BadRequest)?;
+        let alt_text = sanitize_alt_text(form.alt_text.as_str()).ok_or(Status::BadRequest)?;
+        let transcription = form.transcription.as_deref().and_then(sanitize_image_text);
         let path = form.image.persist().map_err(|_| Status::InternalServerError)?;
 
-        create_pending_image(form.post_id.to_string(), path, width, height)
-            .await
-            .map_err(|_| Status::InternalServerError)?
+        create_pending_image(
+            c.sub.to_string(),
+            form.post_id.to_string(),
+            path,
+            width,
+            height,
+            alt_text,
+            transcription,
+        )
+        .await
+        .map_err(|_| Status::InternalServerError)?
     };
 
+    // Counts as editing the draft, so it doesn't expire
+    if status == SubmissionStatus::Draft {
+        update_draft(form.post_id.to_string(), vec![])
+            .await
+            .map_err(|_| Status::InternalServerError)?;
+    }
+
     Ok(Json(pending_image))
 }
 
 #[post("/submissions/<section>/confirm", data = "<post>")]
 pub async fn confirm_submission(
     auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    meta: RequestMeta,
     section: Category,
     post: Form<Strict<PostConfirmationForm>>,
-) -> Result<Json<NotificationBody>, Status> {
-    let _c = auth_header.verify()?;
+) -> Result<ConfirmationResponse, Status> {
+    let claims = auth_header.verify()?;
 
     let id = post.id.to_string();
 
-    let confirmation = confirm_pending_post(section, id, post.comment.clone())
+    let before = get_pending_post(section, id.clone())
         .await
-        .map_err(err_to_status)?;
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let publish_at = post.publish_at.map(|t| *t);
+
+    if publish_at.map_or(false, |t| t <= Utc::now()) {
+        return Err(Status::BadRequest);
+    }
+
+    if !post.override_duplicates {
+        let duplicates = find_similar_excerpts(&before.excerpt, before.id.clone())
+            .await
+            .map_err(|_| Status::InternalServerError)?
+            .into_iter()
+            .filter(|s| s.similarity >= *DUPLICATE_THRESHOLD)
+            .collect::<Vec<_>>();
+
+        if !duplicates.is_empty() {
+            return Ok(ConfirmationResponse::Duplicate(Json(duplicates)));
+        }
+    }
+
+    let note = post.note.as_deref().map(str::trim).filter(|n| !n.is_empty()).map(clean);
+
+    let confirmation = confirm_pending_post(
+        section,
+        id.clone(),
+        claims.sub.to_string(),
+        note,
+        post.comment.clone(),
+        &post.images,
+        publish_at,
+    )
+    .await
+    .map_err(err_to_status)?;
+
+    let (after, response) = match confirmation {
+        Confirmation::Published { post_id, notification } => {
+            let after = db::util::get_post(section, post_id)
+                .await
+                .map_err(|_| Status::InternalServerError)?;
+
+            (
+                after.as_ref().and_then(snapshot),
+                ConfirmationResponse::Published(notification_response(notification)?),
+            )
+        }
+        Confirmation::Awaiting { approvals, required } => {
+            let progress = json!({ "approvals": approvals, "required": required });
+
+            (snapshot(&progress), ConfirmationResponse::Awaiting(Json(progress)))
+        }
+    };
+
+    audit(
+        &claims,
+        meta,
+        AuditAction::ConfirmSubmission,
+        id,
+        snapshot(&before),
+        after,
+    )
+    .await;
+
+    Ok(response)
+}
 
-    Ok(Json(NotificationBody::from(confirmation)))
+/// Approvals only publish the post once the section's policy is met, until
+/// then they're answered with a 202 and how many more are needed
+#[derive(Responder)]
+pub enum ConfirmationResponse {
+    #[response(status = 200)]
+    Published(Json<Option<NotificationBody>>),
+    #[response(status = 202)]
+    Awaiting(Json<Value>),
+    /// Nothing was recorded, the reviewer has to confirm again with
+    /// `override_duplicates` to approve it anyway
+    #[response(status = 409)]
+    Duplicate(Json<Vec<SimilarExcerpt>>),
 }
 
 #[delete("/submissions/<section>/reject", data = "<rejection>")]
 pub async fn reject_submission(
     auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    meta: RequestMeta,
     section: Category,
     rejection: Json<PostRejectionBody>,
-) -> Result<Json<NotificationBody>, Status> {
-    let _c = auth_header.verify()?;
+) -> Result<Json<Option<NotificationBody>>, Status> {
+    let claims = auth_header.verify()?;
 
     let id = rejection.submission_id.to_string();
 
-    let rejection = reject_pending_post(section, id, rejection.comment.clone())
+    let before = get_pending_post(section, id.clone())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let reasons = get_active_rejection_reasons(rejection.reasons.clone())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    // Unknown and archived codes are rejected rather than silently dropped
+    if reasons.len() != rejection.reasons.len() {
+        return Err(Status::BadRequest);
+    }
+
+    let comment = match (&rejection.comment, rejection.template_id) {
+        (Some(comment), _) => Some(comment.clone()),
+        (None, Some(template_id)) => {
+            let template = get_canned_response(template_id.to_string())
+                .await
+                .map_err(|_| Status::InternalServerError)?
+                .ok_or(Status::BadRequest)?;
+            let author = get_user_by_id(before.author_id.clone())
+                .await
+                .map_err(|_| Status::InternalServerError)?
+                .map(|u| u.username)
+                .unwrap_or_default();
+            let labels = reasons.iter().map(|r| r.label.as_str()).collect::<Vec<_>>().join(", ");
+
+            Some(fill_placeholders(
+                &template.body,
+                &[
+                    ("author", &author),
+                    ("section", &section.to_string().to_ascii_lowercase()),
+                    ("reasons", &labels),
+                    ("citation", &html_to_text(&before.citation)),
+                ],
+            ))
+        }
+        (None, None) => None,
+    };
+
+    let rejection = reject_pending_post(section, id.clone(), claims.sub.to_string(), reasons, comment)
         .await
         .map_err(err_to_status)?;
 
-    Ok(Json(NotificationBody::from(rejection)))
+    audit(
+        &claims,
+        meta,
+        AuditAction::RejectSubmission,
+        id,
+        snapshot(&before),
+        None,
+    )
+    .await;
+
+    notification_response(rejection)
+}
+
+/// Claims last for a while, claiming again renews it
+#[post("/submissions/<section>/claim", data = "<body>")]
+pub async fn claim_submission(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    section: Category,
+    body: Json<ClaimBody>,
+) -> Result<Json<pending_post::Data>, Status> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    let id = body.id.to_string();
+
+    get_pending_post(section, id.clone())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let claimed = claim_pending_post(id.clone(), sub.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if !claimed {
+        return Err(Status::Locked);
+    }
+
+    let pending = get_pending_post(section, id)
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    Ok(Json(pending))
+}
+
+#[delete("/submissions/<section>/claim", data = "<body>")]
+pub async fn release_submission(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    section: Category,
+    body: Json<ClaimBody>,
+) -> Result<(), Status> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    let id = body.id.to_string();
+
+    get_pending_post(section, id.clone())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let released = release_pending_post(id, sub.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    if released == 0 {
+        return Err(Status::Forbidden);
+    }
+
+    Ok(())
+}
+
+/// Hands the submission to another admin, overriding any existing claim
+#[post("/submissions/<section>/assign", data = "<body>")]
+pub async fn assign_submission(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+    section: Category,
+    body: Json<AssignmentBody>,
+) -> Result<Json<pending_post::Data>, Status> {
+    let _c = auth_header.verify()?;
+
+    let id = body.id.to_string();
+
+    get_pending_post(section, id.clone())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    let reviewer = get_user_by_id(body.reviewer_id.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .ok_or(Status::NotFound)?;
+
+    if reviewer.role != Role::Admin {
+        return Err(Status::BadRequest);
+    }
+
+    let pending = assign_pending_post(id, reviewer.id, body.comment.clone())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(pending))
+}
+
+/// Submissions the admin has claimed or been assigned, expiring soonest first
+#[get("/me/reviews")]
+pub async fn get_my_reviews(
+    auth_header: AuthHeader<{ AuthLevel::Admin }>,
+) -> Result<Json<Vec<pending_post::Data>>, Status> {
+    let Claims { sub, .. } = auth_header.verify()?;
+
+    let pending = get_claimed_pending_posts(sub.to_string())
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(pending))
+}
+
+/// A submission along with the posts and other submissions worded like it
+#[derive(Serialize)]
+pub struct SubmissionBody {
+    #[serde(flatten)]
+    pub submission: pending_post::Data,
+    pub similar: Vec<SimilarExcerpt>,
 }
 
 #[derive(FromForm)]
 pub struct PostConfirmationForm {
     pub(crate) id: UuidField,
+    /// Passed on to the author once the post is published
     pub(crate) comment: Option<String>,
+    /// Kept with the approval, for the other reviewers
+    #[field(validate = len(..2000))]
+    pub(crate) note: Option<String>,
+    pub(crate) images: Vec<ImageMetadataEdit>,
+    /// Keeps the post hidden until then. Only the approval that publishes the
+    /// post decides this.
+    pub(crate) publish_at: Option<TimestampField>,
+    /// Approves it even if it looks like a duplicate, see
+    /// [`DUPLICATE_THRESHOLD`]
+    #[field(default = false)]
+    pub(crate) override_duplicates: bool,
 }
 
 #[derive(FromForm)]
 pub struct ImageSubmissionForm {
     pub(crate) post_id: UuidField,
     pub(crate) image: ImageField,
+    #[field(validate = len(1..1000))]
+    pub(crate) alt_text: String,
+    pub(crate) transcription: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ClaimBody {
+    pub(crate) id: Uuid,
+}
+
+#[derive(Deserialize)]
+pub struct AssignmentBody {
+    pub(crate) id: Uuid,
+    pub(crate) reviewer_id: Uuid,
+    /// Passed on to the reviewer
+    pub(crate) comment: Option<String>,
 }
 
 #[derive(Deserialize)]
 pub struct PostRejectionBody {
     #[serde(rename = "id")]
     pub(crate) submission_id: UuidField,
+    /// Codes of the rejection reasons, can be left out
+    #[serde(default)]
+    pub(crate) reasons: Vec<String>,
+    /// Takes precedence over `template_id`
     pub(crate) comment: Option<String>,
+    /// A canned response to fill in and use as the comment
+    pub(crate) template_id: Option<Uuid>,
 }
 
+/// Markdown, kept as the source the stored HTML is rendered from
 #[derive(FromForm, Deserialize, Sanitize)]
 pub struct PostSubmissionForm {
-    #[sanitize(trim, custom(convert_and_sanitize))]
+    #[sanitize(trim)]
     #[field(validate = len(10..1500))]
     pub excerpt: String,
-    #[sanitize(trim, custom(convert_and_sanitize))]
+    #[sanitize(trim)]
     #[field(validate = len(10..200))]
     pub citation: String,
 }
 
-fn convert_and_sanitize(s: &str) -> String {
-    let md_parse = Parser::new(s);
-    let mut unsafe_html = String::new();
-    html::push_html(&mut unsafe_html, md_parse);
-
-    clean(unsafe_html.as_str())
-}
-
 #[derive(Serialize, Deserialize)]
 pub struct PostSubmissionResponse {
     pub id: String,
@@ -232,11 +581,25 @@ impl<'r> Responder<'r, 'r> for PostSubmissionResponse {
     }
 }
 
-fn err_to_status(e: QueryError) -> Status {
+/// The notification is [`None`] if the author muted that type
+pub(crate) fn notification_response(
+    notif: Option<notification::Data>,
+) -> Result<Json<Option<NotificationBody>>, Status> {
+    let body = notif
+        .map(NotificationBody::try_from)
+        .transpose()
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(body))
+}
+
+pub(crate) fn err_to_status(e: QueryError) -> Status {
     match e {
         QueryError::Deserialize(err) => {
             if err.eq("Not Found") {
                 Status::NotFound
+            } else if err.eq("Locked") {
+                Status::Locked
             } else {
                 Status::InternalServerError
             }
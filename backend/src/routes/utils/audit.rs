@@ -0,0 +1,39 @@
+use crate::{
+    db::{
+        prisma::AuditAction,
+        util::{record_audit_event, AuditEntry},
+    },
+    routes::utils::{headers::RequestMeta, jwt::Claims},
+};
+use serde::Serialize;
+
+/// Records a moderation action in the audit log. The action has already
+/// happened by the time this is called, so failing to record it is only
+/// logged rather than turned into an error response.
+pub async fn audit(
+    claims: &Claims,
+    meta: RequestMeta,
+    action: AuditAction,
+    target_id: String,
+    before: Option<String>,
+    after: Option<String>,
+) {
+    let entry = AuditEntry {
+        actor_id: claims.sub.to_string(),
+        action,
+        target_id,
+        before,
+        after,
+        ip: meta.ip,
+        user_agent: meta.user_agent,
+    };
+
+    if let Err(e) = record_audit_event(entry).await {
+        error!("Couldn't record audit event: {e}");
+    }
+}
+
+/// Serializes the state of something for [`audit`]
+pub fn snapshot<T: Serialize>(value: &T) -> Option<String> {
+    serde_json::to_string(value).ok()
+}
@@ -1,10 +1,11 @@
 use crate::routes::utils::jwt::{verify_api_token, Claims};
+use chrono::{DateTime, FixedOffset};
 use rocket::{
     http::Status,
     request::{FromRequest, Outcome},
     Request,
 };
-use std::marker::ConstParamTy;
+use std::{convert::Infallible, marker::ConstParamTy};
 
 pub struct AuthHeader<const T: AuthLevel = { AuthLevel::User }> {
     pub(crate) token: String,
@@ -58,6 +59,84 @@ impl Verifiable for AuthHeader<{ AuthLevel::Admin }> {
     }
 }
 
+/// The `Last-Event-ID` header, sent by event stream clients when reconnecting
+pub struct LastEventId(pub(crate) Option<String>);
+
+#[rocket::async_trait]
+impl<'a> FromRequest<'a> for LastEventId {
+    type Error = Infallible;
+
+    async fn from_request(request: &'a Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(LastEventId(
+            request.headers().get_one("Last-Event-ID").map(str::to_owned),
+        ))
+    }
+}
+
+/// `If-None-Match` and `If-Modified-Since`, for answering with a 304
+pub struct ConditionalHeaders {
+    pub(crate) if_none_match: Option<String>,
+    pub(crate) if_modified_since: Option<DateTime<FixedOffset>>,
+}
+
+impl ConditionalHeaders {
+    /// Whether the client's copy is still current. `etag` should include the
+    /// surrounding quotes.
+    pub fn is_fresh(&self, etag: &str, last_modified: Option<DateTime<FixedOffset>>) -> bool {
+        // If-Modified-Since is ignored when If-None-Match is present
+        if let Some(tags) = &self.if_none_match {
+            return tags
+                .split(',')
+                .map(|t| t.trim().trim_start_matches("W/"))
+                .any(|t| t == "*" || t == etag);
+        }
+
+        match (self.if_modified_since, last_modified) {
+            // HTTP dates only have second precision
+            (Some(since), Some(modified)) => modified.timestamp() <= since.timestamp(),
+            _ => false,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'a> FromRequest<'a> for ConditionalHeaders {
+    type Error = Infallible;
+
+    async fn from_request(request: &'a Request<'_>) -> Outcome<Self, Self::Error> {
+        let headers = request.headers();
+
+        Outcome::Success(ConditionalHeaders {
+            if_none_match: headers.get_one("If-None-Match").map(str::to_owned),
+            if_modified_since: headers
+                .get_one("If-Modified-Since")
+                .and_then(|d| DateTime::parse_from_rfc2822(d).ok()),
+        })
+    }
+}
+
+/// Where a request came from, recorded alongside audit events
+#[derive(Clone)]
+pub struct RequestMeta {
+    pub(crate) ip: Option<String>,
+    pub(crate) user_agent: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'a> FromRequest<'a> for RequestMeta {
+    type Error = Infallible;
+
+    async fn from_request(request: &'a Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(RequestMeta {
+            ip: request.client_ip().map(|ip| ip.to_string()),
+            user_agent: request
+                .headers()
+                .get_one("User-Agent")
+                .map(|ua| ua.chars().take(512).collect()),
+        })
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, ConstParamTy)]
 pub enum AuthLevel {
     User,
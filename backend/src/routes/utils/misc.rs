@@ -1,15 +1,23 @@
 // Needed because of the default attrs on FromForm
 #![allow(clippy::needless_late_init)]
 
-use image::{io::Reader as ImageReader, ImageError, ImageFormat, ImageOutputFormat};
+use crate::db::util::{PostFilter, PostOrder};
+use ammonia::{clean, Builder};
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, TimeZone, Utc};
+use image::{
+    io::{Limits, Reader as ImageReader},
+    ImageError, ImageFormat, ImageOutputFormat,
+};
 use imagesize::ImageSize;
+use pulldown_cmark::{html, Parser};
 use rocket::{
     data::ToByteUnit,
     form::{DataField, Form, FromFormField, Strict, ValueField},
     http::ContentType,
+    request::FromParam,
 };
 use sanitizer::Sanitize;
-use std::{io::Cursor, ops::Deref, str::FromStr};
+use std::{io, ops::Deref, str::FromStr};
 use uuid::Uuid;
 use validator::{Validate, ValidationError};
 
@@ -39,31 +47,196 @@ impl<'v> FromFormField<'v> for UuidField {
     }
 }
 
-#[derive(FromForm, Copy, Clone)]
+impl<'a> FromParam<'a> for UuidField {
+    type Error = &'a str;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        Uuid::from_str(param).map(UuidField).map_err(|_| param)
+    }
+}
+
+/// An RFC 3339 timestamp, e.g. `2023-06-01T12:00:00+00:00`
+#[derive(Copy, Clone)]
+pub struct TimestampField(pub(crate) DateTime<FixedOffset>);
+
+impl Deref for TimestampField {
+    type Target = DateTime<FixedOffset>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<'v> FromFormField<'v> for TimestampField {
+    fn from_value(field: ValueField<'v>) -> rocket::form::Result<'v, Self> {
+        let ts = DateTime::parse_from_rfc3339(field.value.trim())
+            .map_err(|_| rocket::form::Error::validation("invalid timestamp"))?;
+
+        Ok(TimestampField(ts))
+    }
+}
+
+/// A calendar date, e.g. `2023-06-01`
+#[derive(Copy, Clone)]
+pub struct DateField(pub(crate) NaiveDate);
+
+impl Deref for DateField {
+    type Target = NaiveDate;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<'v> FromFormField<'v> for DateField {
+    fn from_value(field: ValueField<'v>) -> rocket::form::Result<'v, Self> {
+        let date = NaiveDate::parse_from_str(field.value.trim(), "%Y-%m-%d")
+            .map_err(|_| rocket::form::Error::validation("invalid date"))?;
+
+        Ok(DateField(date))
+    }
+}
+
+/// Anything above this is clamped to it
+pub const MAX_PER_PAGE: u32 = 100;
+
+#[derive(FromForm, Clone)]
 pub struct PaginationFields {
     #[field(default = 1)]
     pub page: u32,
     #[field(default = 10)]
     pub per_page: u32,
+    /// Takes precedence over `page` on the listings that support it
+    pub cursor: Option<Cursor>,
 }
 
 impl PaginationFields {
+    pub fn per_page(&self) -> u32 {
+        self.per_page.clamp(1, MAX_PER_PAGE)
+    }
+
     pub fn skip(&self) -> i64 {
-        if self.page == 1 {
-            0
-        } else {
-            let ret = (self.page - 1) * self.per_page;
+        // Page 0 is treated as the first one
+        (i64::from(self.page.max(1)) - 1) * i64::from(self.per_page())
+    }
+}
+
+/// Sorting, date range and pagination for post and submission listings. These
+/// are all in one struct because a route can only have one trailing query
+/// parameter.
+#[derive(FromForm, Clone)]
+pub struct ListingFields {
+    #[field(default = 1)]
+    pub page: u32,
+    #[field(default = 10)]
+    pub per_page: u32,
+    pub cursor: Option<Cursor>,
+    pub sort: Option<SortField>,
+    /// Only used when sorting randomly, defaults to the current day so pages
+    /// stay consistent for a while without one
+    pub seed: Option<u64>,
+    pub since: Option<TimestampField>,
+    pub until: Option<TimestampField>,
+}
 
-            ret.into()
+impl ListingFields {
+    pub fn pagination(&self) -> PaginationFields {
+        PaginationFields {
+            page: self.page,
+            per_page: self.per_page,
+            cursor: self.cursor.clone(),
+        }
+    }
+
+    pub fn order(&self) -> PostOrder {
+        match self.sort.unwrap_or(SortField::Newest) {
+            SortField::Newest => PostOrder::Newest,
+            SortField::Oldest => PostOrder::Oldest,
+            SortField::Submitted => PostOrder::Submitted,
+            SortField::Popular => PostOrder::Popular,
+            SortField::Random => PostOrder::Random {
+                seed: self
+                    .seed
+                    .unwrap_or_else(|| Utc::now().date_naive().num_days_from_ce() as u64),
+            },
+        }
+    }
+
+    /// Fills in the date range, the rest is left to the caller
+    pub fn filter(&self) -> PostFilter {
+        PostFilter {
+            since: self.since.map(|t| *t),
+            until: self.until.map(|t| *t),
+            ..Default::default()
         }
     }
 }
 
+#[derive(Copy, Clone, FromFormField)]
+pub enum SortField {
+    Newest,
+    Oldest,
+    Submitted,
+    Random,
+    Popular,
+}
+
+/// An opaque position in a listing, made from the last item of a page and
+/// used to fetch the one after it
+#[derive(Clone)]
+pub struct Cursor {
+    pub(crate) at: DateTime<FixedOffset>,
+    pub(crate) id: String,
+}
+
+impl Cursor {
+    pub fn new(at: DateTime<FixedOffset>, id: String) -> Self {
+        Cursor { at, id }
+    }
+
+    pub fn encode(&self) -> String {
+        hex::encode(format!("{}|{}", self.at.timestamp_millis(), self.id))
+    }
+}
+
+impl<'v> FromFormField<'v> for Cursor {
+    fn from_value(field: ValueField<'v>) -> rocket::form::Result<'v, Self> {
+        let invalid = || rocket::form::Error::validation("invalid cursor");
+
+        let decoded = hex::decode(field.value.trim()).map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        let (millis, id) = decoded.split_once('|').ok_or_else(invalid)?;
+
+        let at = millis
+            .parse()
+            .ok()
+            .and_then(|m| Utc.timestamp_millis_opt(m).single())
+            .ok_or_else(invalid)?;
+        let id = Uuid::from_str(id).map_err(|_| invalid())?;
+
+        Ok(Cursor::new(at.into(), id.to_string()))
+    }
+}
+
+/// Reviewer edits to a pending image's accessibility metadata, applied when
+/// its post gets confirmed
+#[derive(FromForm)]
+pub struct ImageMetadataEdit {
+    pub path: String,
+    #[field(validate = len(1..1000))]
+    pub alt_text: Option<String>,
+    /// An empty string removes the transcription
+    pub transcription: Option<String>,
+}
+
+/// Uploads with more pixels than this are rejected before being decoded
+pub const MAX_IMAGE_PIXELS: u64 = 40_000_000;
+
 pub struct ImageField {
     pub(crate) width: usize,
     pub(crate) height: usize,
     bytes: Vec<u8>,
-    /// Can only be PNG or JPEG
+    /// Can only be PNG or JPEG, other formats are converted to PNG
     format: ImageFormat,
 }
 
@@ -89,6 +262,12 @@ impl ImageField {
             _ => unimplemented!("There's really no reason that this should be reached, and if it is that means there's a bug in the FromFormField impl"),
         }
     }
+
+    /// Removes an image previously saved by [`ImageField::persist`], takes the
+    /// path in the same form it was returned.
+    pub async fn remove_persisted(path: &str) -> std::io::Result<()> {
+        tokio::fs::remove_file(path.trim_start_matches('/')).await
+    }
 }
 
 #[async_trait]
@@ -103,86 +282,198 @@ impl<'r> FromFormField<'r> for ImageField {
         let limit = field.request.limits().get("image").unwrap_or(4.mebibytes());
 
         let req_ct = field.content_type;
-        let (png_ct, jpeg_ct) = (ContentType::new("image", "png"), ContentType::new("image", "jpeg"));
-
-        if req_ct != png_ct && req_ct != jpeg_ct {
-            return Err(rocket::form::Error::validation("Not a PNG or JPEG image"))?;
-        }
+        let input_format = match [
+            (ContentType::PNG, ImageFormat::Png),
+            (ContentType::JPEG, ImageFormat::Jpeg),
+            (ContentType::WEBP, ImageFormat::WebP),
+            (ContentType::GIF, ImageFormat::Gif),
+        ]
+        .into_iter()
+        .find(|(ct, _)| *ct == req_ct)
+        {
+            Some((_, format)) => format,
+            None => return Err(rocket::form::Error::validation("Not a PNG, JPEG, WebP or GIF image"))?,
+        };
 
-        let mut bytes = match field.data.open(limit).into_bytes().await {
+        let bytes = match field.data.open(limit).into_bytes().await {
             Ok(bytes) if bytes.is_complete() => bytes.into_inner(),
             Ok(_) => return Err((None, Some(limit)))?,
             // TODO: Figure out how to make this thing actually return what I want it to
             Err(_) => return internal_server_error?,
         };
 
-        if let Ok(ImageSize { width, height }) = imagesize::blob_size(&bytes) {
-            let format;
+        // Only reads the header, so this is checked before anything gets decoded
+        match imagesize::blob_size(&bytes) {
+            Ok(ImageSize { width, height }) if width.saturating_mul(height) as u64 <= MAX_IMAGE_PIXELS => {}
+            Ok(_) => return Err(rocket::form::Error::validation("Image has too many pixels"))?,
+            Err(_) => return Err(rocket::form::Error::validation("Bad image"))?,
+        }
 
-            if req_ct == jpeg_ct {
-                let mut cursor = Cursor::new(bytes);
-                let mut cursor_2 = Cursor::new(vec![]);
+        let mut cursor = io::Cursor::new(bytes);
 
-                let rotation = {
-                    let exif_reader = exif::Reader::new();
+        let rotation = if input_format == ImageFormat::Jpeg {
+            let exif_reader = exif::Reader::new();
 
-                    match exif_reader.read_from_container(&mut cursor) {
-                        Ok(exif) => match exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY) {
-                            Some(orientation) => match orientation.value.get_uint(0) {
-                                Some(v @ 1..=8) => v,
-                                _ => 0,
-                            },
-                            _ => 0,
-                        },
+            let rotation = match exif_reader.read_from_container(&mut cursor) {
+                Ok(exif) => match exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY) {
+                    Some(orientation) => match orientation.value.get_uint(0) {
+                        Some(v @ 1..=8) => v,
                         _ => 0,
-                    }
-                };
-
-                cursor.set_position(0);
-
-                // Encoding jpeg again to erase exif
-                let img = if let Ok(r) = ImageReader::new(cursor).with_guessed_format() {
-                    if let Ok(i) = r.decode() {
-                        i
-                    } else {
-                        return internal_server_error?;
-                    }
-                } else {
-                    return internal_server_error?;
-                };
-
-                let result = match &rotation {
-                    2 => img.fliph(),
-                    3 => img.rotate180(),
-                    4 => img.rotate180().fliph(),
-                    5 => img.rotate90().fliph(),
-                    6 => img.rotate90(),
-                    7 => img.rotate270().fliph(),
-                    8 => img.rotate270(),
-                    _ => img,
-                }
-                .write_to(&mut cursor_2, ImageOutputFormat::Jpeg(100));
+                    },
+                    _ => 0,
+                },
+                _ => 0,
+            };
+
+            cursor.set_position(0);
+
+            rotation
+        } else {
+            0
+        };
+
+        // Everything gets decoded and encoded again, which drops EXIF and any
+        // PNG text chunks. Only the first frame of animated GIFs is kept.
+        let img = {
+            let mut reader = ImageReader::with_format(cursor, input_format);
 
-                if result.is_err() {
-                    return internal_server_error?;
+            let mut limits = Limits::default();
+            // 16 bit RGBA is the largest pixel any of the accepted formats decode to
+            limits.max_alloc = Some(MAX_IMAGE_PIXELS * 8);
+            reader.limits(limits);
+
+            match reader.decode() {
+                Ok(i) => i,
+                Err(ImageError::Limits(_)) => {
+                    return Err(rocket::form::Error::validation("Image has too many pixels"))?
                 }
+                Err(_) => return Err(rocket::form::Error::validation("Bad image"))?,
+            }
+        };
 
-                bytes = cursor_2.into_inner();
-                format = ImageFormat::Jpeg;
-            } else {
-                format = ImageFormat::Png;
+        let img = match &rotation {
+            2 => img.fliph(),
+            3 => img.rotate180(),
+            4 => img.rotate180().fliph(),
+            5 => img.rotate90().fliph(),
+            6 => img.rotate90(),
+            7 => img.rotate270().fliph(),
+            8 => img.rotate270(),
+            _ => img,
+        };
+
+        let (format, output_format) = match input_format {
+            ImageFormat::Jpeg => (ImageFormat::Jpeg, ImageOutputFormat::Jpeg(100)),
+            _ => (ImageFormat::Png, ImageOutputFormat::Png),
+        };
+
+        let mut output = io::Cursor::new(vec![]);
+
+        if img.write_to(&mut output, output_format).is_err() {
+            return internal_server_error?;
+        }
+
+        Ok(ImageField {
+            // Taken after rotating, since that can swap them
+            width: img.width() as usize,
+            height: img.height() as usize,
+            bytes: output.into_inner(),
+            format,
+        })
+    }
+}
+
+pub fn convert_and_sanitize(s: &str) -> String {
+    let md_parse = Parser::new(s);
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, md_parse);
+
+    clean(unsafe_html.as_str())
+}
+
+/// Strips the tags from sanitized HTML, decoding the entities ammonia
+/// escapes. Not meant for arbitrary HTML.
+pub fn html_to_text(html: &str) -> String {
+    // Tags after which a space is needed to keep words apart
+    const BREAKS: [&str; 12] = [
+        "p",
+        "br",
+        "li",
+        "blockquote",
+        "pre",
+        "h1",
+        "h2",
+        "h3",
+        "h4",
+        "h5",
+        "h6",
+        "hr",
+    ];
+
+    let mut text = String::with_capacity(html.len());
+    let mut tag: Option<String> = None;
+
+    for c in html.chars() {
+        if let Some(t) = tag.as_mut() {
+            if c != '>' {
+                t.push(c);
+                continue;
             }
 
-            Ok(ImageField {
-                width,
-                height,
-                bytes,
-                format,
-            })
+            let name = t
+                .trim_start_matches('/')
+                .split(|c: char| c.is_whitespace() || c == '/')
+                .next()
+                .unwrap_or_default();
+
+            if BREAKS.contains(&name) {
+                text.push(' ');
+            }
+
+            tag = None;
+        } else if c == '<' {
+            tag = Some(String::new());
         } else {
-            Err(rocket::form::Error::validation("Bad image"))?
+            text.push(c);
         }
     }
+
+    let text = text
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&");
+
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Longest alt text that fits in its column, in characters
+pub const MAX_ALT_TEXT_LENGTH: usize = 1000;
+
+/// Alt text ends up in an HTML attribute, so it's stored with every tag removed
+/// and the rest escaped. Returns [`None`] if no text is left, or if it's longer
+/// than [`MAX_ALT_TEXT_LENGTH`].
+pub fn sanitize_alt_text(s: &str) -> Option<String> {
+    // Cleaned before anything is decoded, so escaped markup like
+    // `&lt;script&gt;` stays escaped instead of turning into a tag
+    let text = Builder::empty().clean(s).to_string();
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    (!html_to_text(&text).is_empty() && text.chars().count() <= MAX_ALT_TEXT_LENGTH).then_some(text)
+}
+
+/// Used for transcriptions, returns [`None`] if nothing is left after
+/// sanitization
+pub fn sanitize_image_text(s: &str) -> Option<String> {
+    let s = s.trim();
+
+    if s.is_empty() {
+        return None;
+    }
+
+    Some(convert_and_sanitize(s)).filter(|s| !s.is_empty())
 }
 
 pub fn validate_username(username: &str) -> Result<(), ValidationError> {
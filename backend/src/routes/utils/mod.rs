@@ -1,3 +1,4 @@
+pub mod audit;
 pub mod headers;
 pub mod jwt;
 pub mod misc;
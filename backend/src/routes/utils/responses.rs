@@ -1,12 +1,15 @@
 use crate::db::{
     prisma::{notification, NotificationType},
-    util::NotificationContent,
+    util::{NextPage, NotificationContent, Page, SubmissionViolation},
 };
+use chrono::{DateTime, FixedOffset, Utc};
 use rocket::{
+    http::{ContentType, Status},
     response::Responder,
     serde::{json::Json, Deserialize, Serialize},
     Request, Response,
 };
+use std::io::Cursor;
 
 #[derive(Serialize, Deserialize)]
 pub struct LoginResponse {
@@ -27,11 +30,14 @@ pub struct NotificationBody {
     pub read: bool,
     pub n_type: NotificationType,
     pub content: NotificationContent,
+    pub expires_at: Option<chrono::DateTime<chrono::FixedOffset>>,
 }
 
-impl From<notification::Data> for NotificationBody {
-    fn from(value: notification::Data) -> Self {
-        let content = serde_json::from_str::<NotificationContent>(value.content.as_str()).unwrap();
+impl TryFrom<notification::Data> for NotificationBody {
+    type Error = serde_json::Error;
+
+    fn try_from(value: notification::Data) -> Result<Self, Self::Error> {
+        let content = NotificationContent::from_stored(value.content.as_str())?;
 
         macro_rules! _self {
             { $($f:ident),* } => {
@@ -46,6 +52,150 @@ impl From<notification::Data> for NotificationBody {
             }
         }
 
-        _self! { id, created_at, recipient_id, read, n_type }
+        Ok(_self! { id, created_at, recipient_id, read, n_type, expires_at })
+    }
+}
+
+/// A body that clients can cache, answered with a 304 and no body when their
+/// copy is still current
+// Only ever built to be sent right away, so the size difference doesn't matter
+#[allow(clippy::large_enum_variant)]
+pub enum CachedResponse {
+    NotModified {
+        etag: String,
+    },
+    Fresh {
+        body: String,
+        content_type: ContentType,
+        etag: String,
+        last_modified: Option<DateTime<FixedOffset>>,
+    },
+}
+
+impl<'r> Responder<'r, 'static> for CachedResponse {
+    fn respond_to(self, _: &'r Request<'_>) -> rocket::response::Result<'static> {
+        match self {
+            CachedResponse::NotModified { etag } => Response::build()
+                .status(Status::NotModified)
+                .raw_header("ETag", etag)
+                .ok(),
+            CachedResponse::Fresh {
+                body,
+                content_type,
+                etag,
+                last_modified,
+            } => {
+                let mut response = Response::build();
+
+                response
+                    .header(content_type)
+                    .raw_header("ETag", etag)
+                    .raw_header("Cache-Control", "public, max-age=300");
+
+                if let Some(last_modified) = last_modified {
+                    let http_date = last_modified
+                        .with_timezone(&Utc)
+                        .format("%a, %d %b %Y %H:%M:%S GMT")
+                        .to_string();
+
+                    response.raw_header("Last-Modified", http_date);
+                }
+
+                response.sized_body(body.len(), Cursor::new(body)).ok()
+            }
+        }
+    }
+}
+
+/// Sent as a file to download rather than shown inline
+pub struct Download {
+    pub filename: String,
+    pub content_type: ContentType,
+    pub body: String,
+}
+
+impl<'r> Responder<'r, 'static> for Download {
+    fn respond_to(self, _: &'r Request<'_>) -> rocket::response::Result<'static> {
+        Response::build()
+            .header(self.content_type)
+            .raw_header(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", self.filename),
+            )
+            .sized_body(self.body.len(), Cursor::new(self.body))
+            .ok()
+    }
+}
+
+/// A refused submission is explained in the body, so clients can tell the
+/// author what to do about it
+pub enum SubmissionError {
+    Refused(SubmissionViolation),
+    Status(Status),
+}
+
+impl From<Status> for SubmissionError {
+    fn from(status: Status) -> Self {
+        Self::Status(status)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for SubmissionError {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let violation = match self {
+            SubmissionError::Refused(violation) => violation,
+            SubmissionError::Status(status) => return Err(status),
+        };
+
+        let status = match violation {
+            SubmissionViolation::AccountTooNew { .. } => Status::Forbidden,
+            SubmissionViolation::PendingQuota { .. } | SubmissionViolation::UploadQuota { .. } => {
+                Status::TooManyRequests
+            }
+            SubmissionViolation::DuplicateExcerpt => Status::Conflict,
+        };
+
+        Response::build_from(Json(violation).respond_to(request)?)
+            .status(status)
+            .ok()
+    }
+}
+
+/// A page of a listing, sent as a JSON array. The total goes in `X-Total-Count`
+/// and links to the first and next pages in `Link`.
+pub struct Paginated<T>(pub Page<T>);
+
+impl<'r, T: Serialize> Responder<'r, 'static> for Paginated<T> {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let Page { items, total, next } = self.0;
+
+        // Everything but the position is kept, so filters carry over to the links
+        let query = request
+            .uri()
+            .query()
+            .map(|q| {
+                q.raw_segments()
+                    .filter(|s| !matches!(s.as_str().split('=').next(), Some("page" | "cursor")))
+                    .map(|s| format!("{s}&"))
+                    .collect::<String>()
+            })
+            .unwrap_or_default();
+        let path = request.uri().path();
+
+        let mut links = vec![format!("<{path}?{query}page=1>; rel=\"first\"")];
+
+        if let Some(next) = next {
+            let position = match next {
+                NextPage::Cursor(cursor) => format!("cursor={}", cursor.encode()),
+                NextPage::Offset(page) => format!("page={page}"),
+            };
+
+            links.push(format!("<{path}?{query}{position}>; rel=\"next\""));
+        }
+
+        Response::build_from(Json(items).respond_to(request)?)
+            .raw_header("X-Total-Count", total.to_string())
+            .raw_header("Link", links.join(", "))
+            .ok()
     }
 }
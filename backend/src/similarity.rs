@@ -0,0 +1,173 @@
+//! Finds excerpts that are worded the same or nearly so, by comparing the sets
+//! of word shingles in their plain text.
+//!
+//! Comparing against every excerpt would mean loading all of them, so each one
+//! is stored with the bands of its MinHash signature. Only excerpts sharing a
+//! band are likely to be similar enough, and only those get compared.
+
+use crate::routes::utils::misc::html_to_text;
+use lazy_static::lazy_static;
+use sha2::{Digest, Sha256};
+use std::{collections::HashSet, env};
+
+/// Words per shingle
+const SHINGLE_SIZE: usize = 3;
+/// Least similarity worth showing to reviewers
+pub const MIN_SIMILARITY: f64 = 0.3;
+/// Signature values per band. With [`BANDS`], excerpts [`MIN_SIMILARITY`]
+/// alike share a band about 95% of the time.
+const BAND_ROWS: usize = 2;
+const BANDS: usize = 32;
+
+lazy_static! {
+    /// Similarity above which confirming a submission needs an override
+    pub static ref DUPLICATE_THRESHOLD: f64 = env::var("DUPLICATE_THRESHOLD")
+        .ok()
+        .and_then(|t| t.parse().ok())
+        .filter(|t| (0.0..=1.0).contains(t))
+        .unwrap_or(0.8);
+}
+
+/// The shingles of an excerpt, hashed so they're cheap to compare
+pub struct Shingles(HashSet<u64>);
+
+impl Shingles {
+    /// From sanitized HTML, as excerpts are stored
+    pub fn from_html(html: &str) -> Self {
+        Self::from_text(&html_to_text(html))
+    }
+
+    /// Case, punctuation and spacing are ignored. Texts shorter than a
+    /// shingle make up a single one.
+    pub fn from_text(text: &str) -> Self {
+        let normalized = text
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+            .flat_map(char::to_lowercase)
+            .collect::<String>();
+        let words = normalized.split_whitespace().collect::<Vec<_>>();
+
+        let shingles = words
+            .windows(SHINGLE_SIZE.min(words.len()).max(1))
+            .map(|w| hash(&w.join(" ")))
+            .collect();
+
+        Self(shingles)
+    }
+
+    /// The Jaccard index of the two sets, from 0 for nothing in common to 1
+    /// for the same wording
+    pub fn similarity(&self, other: &Self) -> f64 {
+        let union = self.0.union(&other.0).count();
+
+        if union == 0 {
+            return 0.0;
+        }
+
+        self.0.intersection(&other.0).count() as f64 / union as f64
+    }
+
+    /// Hashes of the bands of the MinHash signature, none if there are no
+    /// shingles. Two excerpts share a band with a chance that grows with
+    /// their similarity.
+    pub fn bands(&self) -> Vec<i64> {
+        if self.0.is_empty() {
+            return vec![];
+        }
+
+        let signature = (0..BANDS * BAND_ROWS)
+            .map(|seed| {
+                self.0
+                    .iter()
+                    .map(|&s| permute(s, seed as u64))
+                    .min()
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>();
+
+        signature
+            .chunks(BAND_ROWS)
+            .enumerate()
+            .map(|(band, rows)| {
+                let mut hasher = Sha256::new();
+
+                hasher.update(band.to_le_bytes());
+                rows.iter().for_each(|r| hasher.update(r.to_le_bytes()));
+
+                i64::from_le_bytes(hasher.finalize()[..8].try_into().expect("digest is 32 bytes"))
+            })
+            .collect()
+    }
+}
+
+/// One of a family of shuffles of the shingle hashes, the seed picks which
+fn permute(shingle: u64, seed: u64) -> u64 {
+    let mut z = shingle.wrapping_add(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+    z ^ (z >> 31)
+}
+
+fn hash(shingle: &str) -> u64 {
+    let digest = Sha256::digest(shingle.as_bytes());
+
+    u64::from_le_bytes(digest[..8].try_into().expect("digest is 32 bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_text_has_no_shingles() {
+        let empty = Shingles::from_text("");
+
+        assert!(empty.0.is_empty());
+        assert!(empty.bands().is_empty());
+        assert_eq!(empty.similarity(&Shingles::from_text("")), 0.0);
+        assert_eq!(empty.similarity(&Shingles::from_text("one two three")), 0.0);
+    }
+
+    #[test]
+    fn short_text_is_a_single_shingle() {
+        let short = Shingles::from_text("Hello, World!");
+
+        assert_eq!(short.0.len(), 1);
+        assert_eq!(short.similarity(&Shingles::from_text("hello world")), 1.0);
+        assert_eq!(short.similarity(&Shingles::from_text("hello there")), 0.0);
+    }
+
+    #[test]
+    fn similarity_is_the_jaccard_index() {
+        // Shingles "a b c", "b c d" and "b c d", "c d e"
+        let a = Shingles::from_text("a b c d");
+        let b = Shingles::from_text("b c d e");
+
+        assert_eq!(a.similarity(&b), 1.0 / 3.0);
+        assert_eq!(b.similarity(&a), 1.0 / 3.0);
+        assert_eq!(a.similarity(&a), 1.0);
+        assert_eq!(a.similarity(&Shingles::from_text("w x y z")), 0.0);
+    }
+
+    #[test]
+    fn html_is_compared_as_text() {
+        let html = Shingles::from_html("<p>The <em>quick</em> brown fox</p>");
+
+        assert_eq!(html.similarity(&Shingles::from_text("the quick brown fox")), 1.0);
+    }
+
+    #[test]
+    fn alike_excerpts_share_bands() {
+        let text = "It was the best of times, it was the worst of times, it was the age of wisdom";
+        let same = Shingles::from_text(text).bands();
+        let close = Shingles::from_text(&format!("{text}, it was the age of foolishness")).bands();
+        let other = Shingles::from_text("Call me Ishmael. Some years ago, never mind how long precisely").bands();
+
+        assert_eq!(same.len(), BANDS);
+        assert_eq!(same, Shingles::from_text(text).bands());
+        assert!(close.iter().any(|b| same.contains(b)));
+        assert!(!other.iter().any(|b| same.contains(b)));
+    }
+}
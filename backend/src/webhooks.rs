@@ -0,0 +1,458 @@
+//! Outgoing webhooks for moderation events. Every delivery is logged before
+//! being sent, and failed ones are retried with exponential backoff by the
+//! `webhook_deliveries` job.
+//!
+//! Requests are `POST`ed as JSON, with these headers:
+//! * `X-Abode-Event` - e.g. `post_confirmed`
+//! * `X-Abode-Delivery` - the delivery's ID, stays the same across retries
+//! * `X-Abode-Timestamp` - unix timestamp of the attempt
+//! * `X-Abode-Signature` - `sha256=` followed by the hex encoded HMAC-SHA256 of
+//!   `{timestamp}.{body}`, keyed with the webhook's secret
+
+use crate::db::{
+    prisma::{webhook_delivery, Category, WebhookDeliveryStatus, WebhookEvent},
+    util::{claim_webhook_delivery, create_webhook_deliveries, get_due_webhook_deliveries, update_webhook_delivery},
+};
+use chrono::{DateTime, FixedOffset, Utc};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use prisma_client_rust::QueryError;
+use serde::Serialize;
+use sha2::Sha256;
+use std::time::Duration;
+
+/// Deliveries are marked as failed after this many attempts
+const MAX_ATTEMPTS: i32 = 8;
+/// Delay before the first retry, doubled for every one after it
+const BASE_BACKOFF_SECS: i64 = 30;
+/// How long a delivery is reserved for whoever is sending it
+const LEASE_SECS: i64 = 5 * 60;
+/// How many due deliveries get sent per run of the job
+const BATCH_SIZE: i64 = 100;
+
+lazy_static! {
+    static ref CLIENT: reqwest::Client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap();
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum Event {
+    SubmissionCreated {
+        id: String,
+        section: Category,
+        author_id: String,
+    },
+    PostConfirmed {
+        id: String,
+        submission_id: String,
+        section: Category,
+        url: String,
+        /// The post stays hidden until then when set
+        publish_at: Option<DateTime<FixedOffset>>,
+    },
+    PostRejected {
+        submission_id: String,
+        section: Category,
+        comment: Option<String>,
+    },
+    PostDeleted {
+        id: String,
+        section: Category,
+        comment: Option<String>,
+    },
+    UserSignedUp {
+        id: String,
+        username: String,
+    },
+}
+
+impl Event {
+    pub fn kind(&self) -> WebhookEvent {
+        match self {
+            Event::SubmissionCreated { .. } => WebhookEvent::SubmissionCreated,
+            Event::PostConfirmed { .. } => WebhookEvent::PostConfirmed,
+            Event::PostRejected { .. } => WebhookEvent::PostRejected,
+            Event::PostDeleted { .. } => WebhookEvent::PostDeleted,
+            Event::UserSignedUp { .. } => WebhookEvent::UserSignedUp,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    #[serde(flatten)]
+    event: &'a Event,
+    created_at: chrono::DateTime<Utc>,
+}
+
+/// Logs deliveries for every subscribed webhook and makes the first attempt,
+/// all in the background so the caller isn't held up.
+pub fn emit(event: Event) {
+    rocket::tokio::spawn(async move {
+        let payload = serde_json::to_string(&Payload {
+            event: &event,
+            created_at: Utc::now(),
+        })
+        .unwrap();
+
+        let ids = match create_webhook_deliveries(event.kind(), payload).await {
+            Ok(ids) if ids.is_empty() => return,
+            Ok(ids) => ids,
+            Err(e) => {
+                error!("Couldn't log webhook deliveries: {e}");
+                return;
+            }
+        };
+
+        if let Err(e) = deliver_due(Some(ids)).await {
+            error!("Couldn't deliver webhooks: {e}");
+        }
+    });
+}
+
+/// Sends every due delivery, or only those in `ids`. Returns how many
+/// succeeded.
+pub async fn deliver_due(ids: Option<Vec<String>>) -> Result<usize, QueryError> {
+    let due = get_due_webhook_deliveries(ids, BATCH_SIZE).await?;
+    let mut delivered = 0;
+
+    for delivery in due {
+        let lease_until = Utc::now() + chrono::Duration::seconds(LEASE_SECS);
+
+        if !claim_webhook_delivery(&delivery, lease_until.into()).await? {
+            continue;
+        }
+
+        if attempt(delivery).await? {
+            delivered += 1;
+        }
+    }
+
+    Ok(delivered)
+}
+
+async fn attempt(delivery: webhook_delivery::Data) -> Result<bool, QueryError> {
+    let webhook = delivery
+        .webhook
+        .as_ref()
+        .expect("fetched by get_due_webhook_deliveries");
+
+    let now = Utc::now();
+
+    let res = send(
+        webhook.url.as_str(),
+        webhook.secret.as_str(),
+        delivery.event,
+        delivery.id.as_str(),
+        delivery.payload.as_str(),
+        now.timestamp(),
+    )
+    .await;
+
+    let attempts = delivery.attempts + 1;
+    let mut params = vec![webhook_delivery::attempts::set(attempts)];
+
+    let success = match res {
+        Ok(code) => {
+            params.push(webhook_delivery::last_status_code::set(Some(code.into())));
+            params.push(webhook_delivery::last_error::set(None));
+
+            (200..300).contains(&code)
+        }
+        Err(e) => {
+            params.push(webhook_delivery::last_status_code::set(None));
+            params.push(webhook_delivery::last_error::set(Some(e.to_string())));
+
+            false
+        }
+    };
+
+    match outcome(attempts, success, now) {
+        Outcome::Delivered => {
+            params.push(webhook_delivery::status::set(WebhookDeliveryStatus::Delivered));
+            params.push(webhook_delivery::delivered_at::set(Some(now.into())));
+        }
+        Outcome::Failed => params.push(webhook_delivery::status::set(WebhookDeliveryStatus::Failed)),
+        Outcome::Retry { at } => params.push(webhook_delivery::next_attempt_at::set(at.into())),
+    }
+
+    update_webhook_delivery(delivery.id, params).await?;
+
+    Ok(success)
+}
+
+/// Makes one request, returning the response's status code
+async fn send(
+    url: &str,
+    secret: &str,
+    event: WebhookEvent,
+    delivery_id: &str,
+    payload: &str,
+    timestamp: i64,
+) -> Result<u16, reqwest::Error> {
+    let res = CLIENT
+        .post(url)
+        .header("Content-Type", "application/json")
+        .header("X-Abode-Event", event_name(event))
+        .header("X-Abode-Delivery", delivery_id)
+        .header("X-Abode-Timestamp", timestamp.to_string())
+        .header(
+            "X-Abode-Signature",
+            format!("sha256={}", sign(secret, timestamp, payload)),
+        )
+        .body(payload.to_owned())
+        .send()
+        .await?;
+
+    Ok(res.status().as_u16())
+}
+
+/// What becomes of a delivery after an attempt
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Delivered,
+    Retry {
+        at: DateTime<Utc>,
+    },
+    /// Given up on after [`MAX_ATTEMPTS`]
+    Failed,
+}
+
+/// `attempts` includes the one just made
+fn outcome(attempts: i32, success: bool, now: DateTime<Utc>) -> Outcome {
+    if success {
+        Outcome::Delivered
+    } else if attempts >= MAX_ATTEMPTS {
+        Outcome::Failed
+    } else {
+        Outcome::Retry {
+            at: now + chrono::Duration::seconds(BASE_BACKOFF_SECS << (attempts - 1)),
+        }
+    }
+}
+
+/// Hex encoded HMAC-SHA256 of `{timestamp}.{body}`
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+
+    mac.update(format!("{timestamp}.").as_bytes());
+    mac.update(body.as_bytes());
+
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn event_name(event: WebhookEvent) -> &'static str {
+    match event {
+        WebhookEvent::SubmissionCreated => "submission_created",
+        WebhookEvent::PostConfirmed => "post_confirmed",
+        WebhookEvent::PostRejected => "post_rejected",
+        WebhookEvent::PostDeleted => "post_deleted",
+        WebhookEvent::UserSignedUp => "user_signed_up",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::util::{create_webhook, get_webhook_deliveries, remove_webhook},
+        routes::utils::misc::PaginationFields,
+    };
+    use std::collections::HashMap;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::mpsc,
+    };
+
+    /// A request as the stand-in received it, with lowercase header names
+    struct Received {
+        headers: HashMap<String, String>,
+        body: String,
+    }
+
+    /// Starts a local HTTP server that answers every request with `status`.
+    /// Returns its URL and the requests it gets.
+    async fn stand_in(status: u16) -> (String, mpsc::UnboundedReceiver<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = vec![];
+
+                // Reads the head, then as much of the body as it says there is
+                let received = loop {
+                    let mut chunk = [0; 4096];
+                    let n = stream.read(&mut chunk).await.unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+
+                    let text = String::from_utf8_lossy(&buf).into_owned();
+
+                    let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                        assert!(n > 0, "connection closed before the headers were sent");
+                        continue;
+                    };
+
+                    let headers = head
+                        .lines()
+                        .skip(1)
+                        .filter_map(|l| l.split_once(": "))
+                        .map(|(k, v)| (k.to_ascii_lowercase(), v.to_owned()))
+                        .collect::<HashMap<_, _>>();
+                    let length = headers.get("content-length").map_or(0, |l| l.parse().unwrap());
+
+                    if body.len() >= length || n == 0 {
+                        break Received {
+                            headers,
+                            body: body.to_owned(),
+                        };
+                    }
+                };
+
+                let response = format!("HTTP/1.1 {status} Stand-in\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+                stream.write_all(response.as_bytes()).await.unwrap();
+
+                let _ = tx.send(received);
+            }
+        });
+
+        (url, rx)
+    }
+
+    #[test]
+    fn sign_matches_known_signature() {
+        assert_eq!(
+            sign("shh", 1_700_000_000, r#"{"event":"post_deleted"}"#),
+            "739d6a39af4e25a16d61e8000752303e7b3b74ce2aa9dc3de871fc467ff9c57b"
+        );
+    }
+
+    #[tokio::test]
+    async fn send_signs_the_request() {
+        let (url, mut requests) = stand_in(204).await;
+        let payload = r#"{"event":"post_deleted"}"#;
+
+        let status = send(&url, "shh", WebhookEvent::PostDeleted, "d1", payload, 1_700_000_000)
+            .await
+            .unwrap();
+
+        assert_eq!(status, 204);
+
+        let received = requests.recv().await.unwrap();
+
+        assert_eq!(received.body, payload);
+        assert_eq!(received.headers["x-abode-event"], "post_deleted");
+        assert_eq!(received.headers["x-abode-delivery"], "d1");
+        assert_eq!(received.headers["x-abode-timestamp"], "1700000000");
+        assert_eq!(
+            received.headers["x-abode-signature"],
+            "sha256=739d6a39af4e25a16d61e8000752303e7b3b74ce2aa9dc3de871fc467ff9c57b"
+        );
+    }
+
+    #[tokio::test]
+    async fn send_returns_server_errors() {
+        let (url, _requests) = stand_in(503).await;
+
+        let status = send(&url, "shh", WebhookEvent::PostDeleted, "d1", "{}", 1_700_000_000)
+            .await
+            .unwrap();
+
+        assert_eq!(status, 503);
+    }
+
+    #[test]
+    fn backoff_doubles_with_every_attempt() {
+        let now = Utc::now();
+
+        for attempts in 1..MAX_ATTEMPTS {
+            assert_eq!(
+                outcome(attempts, false, now),
+                Outcome::Retry {
+                    at: now + chrono::Duration::seconds(BASE_BACKOFF_SECS * 2i64.pow(attempts as u32 - 1))
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let now = Utc::now();
+
+        assert!(matches!(outcome(MAX_ATTEMPTS - 1, false, now), Outcome::Retry { .. }));
+        assert_eq!(outcome(MAX_ATTEMPTS, false, now), Outcome::Failed);
+        assert_eq!(outcome(MAX_ATTEMPTS + 1, false, now), Outcome::Failed);
+    }
+
+    #[test]
+    fn successes_are_delivered_on_any_attempt() {
+        let now = Utc::now();
+
+        assert_eq!(outcome(1, true, now), Outcome::Delivered);
+        assert_eq!(outcome(MAX_ATTEMPTS, true, now), Outcome::Delivered);
+    }
+
+    /// Goes through the database, so it needs `DATABASE_URL` pointing at a
+    /// disposable one
+    #[tokio::test]
+    #[ignore = "needs a database"]
+    async fn deliver_due_fails_after_max_attempts() {
+        let (url, mut requests) = stand_in(500).await;
+
+        let webhook = create_webhook(url, "shh".to_owned(), vec![WebhookEvent::PostDeleted])
+            .await
+            .unwrap();
+        let ids = create_webhook_deliveries(WebhookEvent::PostDeleted, "{}".to_owned())
+            .await
+            .unwrap();
+
+        let delivery = || async {
+            get_webhook_deliveries(
+                Some(webhook.id.clone()),
+                None,
+                PaginationFields {
+                    page: 1,
+                    per_page: 10,
+                    cursor: None,
+                },
+            )
+            .await
+            .unwrap()
+            .remove(0)
+        };
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            let before = Utc::now();
+
+            assert_eq!(deliver_due(Some(ids.clone())).await.unwrap(), 0);
+            assert!(requests.recv().await.is_some());
+
+            let d = delivery().await;
+
+            assert_eq!(d.attempts, attempt);
+            assert_eq!(d.last_status_code, Some(500));
+
+            if attempt < MAX_ATTEMPTS {
+                let backoff = chrono::Duration::seconds(BASE_BACKOFF_SECS << (attempt - 1));
+
+                assert_eq!(d.status, WebhookDeliveryStatus::Pending);
+                assert!(d.next_attempt_at >= before + backoff - chrono::Duration::seconds(1));
+
+                // Skips the wait for the next attempt
+                update_webhook_delivery(d.id, vec![webhook_delivery::next_attempt_at::set(Utc::now().into())])
+                    .await
+                    .unwrap();
+            } else {
+                assert_eq!(d.status, WebhookDeliveryStatus::Failed);
+            }
+        }
+
+        remove_webhook(webhook.id).await.unwrap();
+    }
+}